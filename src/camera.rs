@@ -1,8 +1,16 @@
+use core::{cell::Cell, fmt};
+
 use stm32f4::stm32f401;
 
-use cortex_m::asm;
+use cortex_m::{asm, peripheral::DWT};
+
+use crate::{color::Rgb565, constants::{Config, CLK_HZ}, delay::DelayMs, fault::{Fault, FAULTS}, framebuffer::FrameBuffer, init::InitError, sccb::{self, I2cError}, timeout::{retry_with_backoff, wait_while, INIT_ATTEMPTS}};
 
-use crate::{constants::CLK_HZ, display::{ST7735, Display}};
+#[cfg(feature = "display")]
+use crate::display::{ST7735, Display};
+
+#[cfg(feature = "usart")]
+use crate::{crc::{crc16, CRC16_INIT}, usart_debugger::WriteBytes};
 
 /*
     OV7670 Camera
@@ -17,163 +25,497 @@ use crate::{constants::CLK_HZ, display::{ST7735, Display}};
     D5  |PC5|Data[5] (GPIO)
     D3  |PC3|Data[3] (GPIO)
     D1  |PC1|Data[1] (GPIO)
-    RET |3.3|Reset (unused)
+    RET |3.3|Reset (optional GPIO, active low)
     DGND|GND|
     SDA |PB9|SCCB data (I2C1_SDA)
-    HS  |PB3|HSync (GPIO)
+    HS  |PB3|HREF (GPIO)
     XCLK|PA8|External clock (MCO_1)
     D6  |PC6|Data[6] (GPIO)
     D4  |PC4|Data[4] (GPIO)
     D2  |PC2|Data[2] (GPIO)
     D0  |PC0|Data[0] (GPIO)
-    PWDN|GND|Power down (unused)
+    PWDN|GND|Power down (optional GPIO, active high)
+
+    D0-D7 default to PC0-PC7 but can be remapped with `DataBus`
+
+    RET and PWDN are tied off by default. Wiring them to GPIOs and passing
+    `PowerPins` to `with_power_pins` enables `hardware_reset` and `power_down`
+
+    PCLK switches to TIM1_CH2 (AF1) while a `DmaCapture` is active
+
+    The HS pin carries HREF by default (COM10[6] clear). HREF is only active
+    while valid pixels are clocked out, so capture is gated on it and
+    horizontal blanking is skipped even if PCLK keeps toggling. If COM10[6]
+    is set the pin carries HSYNC instead, a short pulse at the start of each
+    line, and blanking bytes would leak into the row buffer.
 */
 
+/// GPIO port a camera data line is wired to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Port {
+    A,
+    B,
+    C
+}
+
+/// Mapping of the OV7670 D0-D7 data lines onto GPIO pins
+///
+/// Pins must not overlap the SCCB, sync, clock or display pins. `new`
+/// rejects a bus on the SCCB, sync, XCLK or USART2 pins.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DataBus {
+    /// D0-D7 on consecutive pins `offset..offset + 8` of one port (fast path)
+    Contiguous { port: Port, offset: u8 },
+    /// D0-D7 on arbitrary `(port, pin)` pairs, indexed by data bit
+    Pins([(Port, u8); 8])
+}
+
+impl DataBus {
+
+    // `(port, pin)` of D0-D7, unchecked
+    fn pins(self) -> [(Port, u8); 8] {
+        match self {
+            DataBus::Contiguous { port, offset } => core::array::from_fn(|bit| (port, offset + bit as u8)),
+            DataBus::Pins(pins) => pins
+        }
+    }
+}
+
+impl Default for DataBus {
+    fn default() -> Self {
+        DataBus::Contiguous { port: Port::C, offset: 0 }
+    }
+}
+
+/// Optional GPIOs driving the OV7670 RESET and PWDN lines
+///
+/// `None` means the line is tied off (RESET to 3.3V, PWDN to GND). Lines
+/// must not overlap each other, the data bus, or the SCCB, sync, XCLK,
+/// USART2 or display SPI pins.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PowerPins {
+    /// Reset, active low
+    pub reset: Option<(Port, u8)>,
+    /// Power down, active high
+    pub pwdn: Option<(Port, u8)>
+}
+
+/// Framing options for `OV7670::stream_frame`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamConfig {
+    /// Append a CRC-16/CCITT over the pixel payload, for long or noisy links
+    pub crc: bool
+}
+
+/// Multiplier of the sensor's PLL (DBLV[7:6]), see `OV7670::set_pll`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PllMult {
+    /// PLL off, the internal clock runs straight from XCLK (reset default)
+    Bypass,
+    X4,
+    X6,
+    X8
+}
+
+impl PllMult {
+
+    const ALL: [PllMult; 4] = [PllMult::Bypass, PllMult::X4, PllMult::X6, PllMult::X8];
+
+    pub const fn multiplier(self) -> u32 {
+        match self {
+            PllMult::Bypass => 1,
+            PllMult::X4 => 4,
+            PllMult::X6 => 6,
+            PllMult::X8 => 8
+        }
+    }
+
+    // DBLV[7:6] field value
+    const fn bits(self) -> u8 {
+        match self {
+            PllMult::Bypass => 0x00,
+            PllMult::X4 => 0x40,
+            PllMult::X6 => 0x80,
+            PllMult::X8 => 0xC0
+        }
+    }
+}
+
+/// Maximum gain the AGC may apply in low light
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AgcCeiling {
+    X2,
+    X4,
+    X8,
+    X16,
+    X32,
+    X64,
+    X128
+}
+
+/// Metering used by the sensor's automatic exposure control
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AecAlgorithm {
+    /// Average luminance against the AEW/AEB window
+    Average,
+    /// Histogram based, more robust to small bright areas
+    Histogram
+}
+
+/// Special color effect applied by the sensor's DSP, see `OV7670::set_effect`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorEffect {
+    None,
+    /// Inverted image (TSLB[5])
+    Negative,
+    /// Fixed warm tint (TSLB[4] with MANU/MANV)
+    Sepia,
+    /// Grayscale (TSLB[4] with neutral MANU/MANV)
+    BlackWhite
+}
+
+/// Channel order of the sensor's RGB 565 output, see `OV7670::set_rgb_order`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RgbOrder {
+    /// Red in the high bits (default)
+    Rgb,
+    /// Blue in the high bits
+    Bgr
+}
+
+/// Capture signal that stopped toggling
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CaptureError {
+    VsyncTimeout,
+    HrefTimeout,
+    PclkTimeout
+}
+
+/// Timing and loss figures for one frame, see `OV7670::capture_frame`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CaptureStats {
+    /// Rows captured into the frame
+    pub rows: u32,
+    /// Rows whose HREF start was missed, so capture began partway through,
+    /// plus rows lost entirely when VSYNC ended the frame before its last row
+    pub dropped_hsync: u32,
+    /// Core cycles from VSYNC to the end of the last row
+    ///
+    /// Always 0 unless the DWT cycle counter has been enabled
+    /// (`DCB::enable_trace` and `DWT::enable_cycle_counter`).
+    pub duration_cycles: u32
+}
+
+impl CaptureStats {
+
+    // Start timing a frame
+    pub(crate) fn start() -> (Self, u32) {
+        (CaptureStats::default(), DWT::cycle_count())
+    }
+
+    // Capture `rows` rows with `row`, tallying them
+    //
+    // `row(y)` captures row `y` and returns whether its start was missed, or
+    // `None` if VSYNC ended the frame first; the rows still expected then
+    // count as dropped
+    pub(crate) fn capture_rows(
+        &mut self,
+        rows: usize,
+        mut row: impl FnMut(usize) -> Result<Option<bool>, CaptureError>
+    ) -> Result<(), CaptureError> {
+
+        for y in 0..rows {
+            match row(y)? {
+                Some(late) => {
+                    self.dropped_hsync += late as u32;
+                    self.rows += 1;
+                }
+                None => {
+                    self.dropped_hsync += (rows - y) as u32;
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Record the end of the frame started at `start`
+    pub(crate) fn finish(mut self, start: u32) -> Self {
+        if DWT::cycle_counter_enabled() {
+            self.duration_cycles = DWT::cycle_count().wrapping_sub(start);
+        }
+        self
+    }
+}
+
 pub trait Camera {
 
     /// Setup and turn on the camera
-    fn calibrate(&self);
+    fn calibrate(&self, delay: &mut impl DelayMs) -> Result<(), I2cError> {
+        self.calibrate_with(delay, true)
+    }
 
-    fn draw_frame(&self, display: &ST7735);
+    /// Setup the camera, optionally skipping the register reset
+    ///
+    /// Without the reset (and its ~120ms settle) only the registers set by
+    /// `calibrate` are rewritten. Setter changes to other registers are kept,
+    /// but those made to the calibrated ones (frame rate, scaling, gain,
+    /// AEC/AWB, color matrix) are overwritten and must be reapplied.
+    fn calibrate_with(&self, delay: &mut impl DelayMs, reset: bool) -> Result<(), I2cError>;
+
+    /// Synchronize to the start of the next frame (VSYNC)
+    fn wait_frame_start(&self) -> Result<(), CaptureError>;
+
+    /// Capture the next line of the current frame into `buf`
+    ///
+    /// Pixels beyond the length of `buf` are dropped.
+    fn capture_line(&self, buf: &mut [Rgb565]) -> Result<(), CaptureError>;
+
+    /// Capture a frame and draw it to the display
+    ///
+    /// Fails if the camera stops clocking out VSYNC, HREF or PCLK edges.
+    #[cfg(feature = "display")]
+    fn draw_frame(&self, display: &ST7735) -> Result<(), CaptureError>;
+
+    /// Capture a frame and draw it nearest-neighbor scaled to `dst_w`×`dst_h`
+    ///
+    /// `dst_w` is the number of pixels per drawn row and `dst_h` the number of
+    /// rows. Widths beyond the camera row width are clamped.
+    #[cfg(feature = "display")]
+    fn draw_frame_scaled(&self, display: &ST7735, dst_w: u32, dst_h: u32) -> Result<(), CaptureError>;
+
+    /// Stream frames to the display until a capture fails
+    ///
+    /// `on_frame` is called once per frame, just after VSYNC and before the
+    /// first line, to poll input or change settings between frames. It must
+    /// return within vertical blanking or the frame is lost.
+    #[cfg(feature = "display")]
+    fn run(&self, display: &ST7735, on_frame: &mut impl FnMut()) -> CaptureError;
 }
 
 pub struct OV7670<'a> {
+    rcc: &'a stm32f401::RCC,
     gpioa: &'a stm32f401::GPIOA,
     gpiob: &'a stm32f401::GPIOB,
     gpioc: &'a stm32f401::GPIOC,
-    i2c1: stm32f401::I2C1
+    i2c1: stm32f401::I2C1,
+    data_bus: DataBus,
+    power_pins: PowerPins,
+    config: Config,
+    // APB1 clock I2C1 timing was last computed from, reused by `recover_bus`
+    apb1_hz: Cell<u32>,
+    pub(crate) pclk_inverted: Cell<bool>,
+    href_inverted: Cell<bool>,
+    vsync_inverted: Cell<bool>,
+    #[cfg(feature = "display")]
+    debug_overlay: Cell<bool>,
+    blend: Cell<u8>,
+    settle_frames: Cell<u8>,
+    settling: Cell<u8>,
+    // Lines per frame at the output size last set with `set_scaling`
+    frame_height: Cell<u32>,
+    stream_config: Cell<StreamConfig>,
+    pub(crate) swap_rb: Cell<bool>
 }
 
-impl<'a> Camera for OV7670<'a> {
+// The borrowed PAC ports aren't `Sync`, but they're only accessed from one
+// core, so the driver can move into a `Shared` handle for interrupt handlers
+unsafe impl Send for OV7670<'_> {}
 
-    fn calibrate(&self) {
+impl<'a> Camera for OV7670<'a> {
 
-        const COM7_ADDR: u8 = 0x12;
-        const COM7_RGB_SELECT: u8 = 0x04;
-        const COM7_QVGA_SELECT: u8 = 0x10;
-        const COM7_RESET: u8 = 0x80;
+    fn calibrate_with(&self, delay: &mut impl DelayMs, reset: bool) -> Result<(), I2cError> {
 
-        const CLKRC_ADDR: u8 = 0x11;
-        const CLKRC_PRESCALER: u8 = 0x01; // CLK = CLK_IN/(PRESCALER+1)
+        // Reset all registers to default values
+        if reset {
+            self.soft_reset(delay)?;
+        }
 
-        const COM3_ADDR: u8 = 0x0C;
-        const COM3_DCW_EN: u8 = 0x04;
+        self.write_registers(OV7670::DEFAULT_REGISTERS)?;
 
-        const COM14_ADDR: u8 = 0x3E;
-        const COM14_MANUAL_SCALE_EN: u8 = 0x08;
-        const COM14_DCW_AND_PCLK_SCALE_EN: u8 = 0x10;
-        const COM14_PCLK_DIVIDER: u8 = 0x01; // Divide by 2
+        // Downsample by 2 to the frame size
+        self.set_scaling(2, 2)?;
 
-        const SCALING_XSC_ADDR: u8 = 0x70;
-        const SCALING_XSC_HORZ_SCALE_FACTOR: u8 = 0x3A; // Default
+        let (coeffs, signs) = OV7670::DEFAULT_COLOR_MATRIX;
+        self.set_color_matrix(coeffs, signs)?;
 
-        const SCALING_YSC_ADDR: u8 = 0x71;
-        const SCALING_YSC_VERT_SCALE_FACTOR: u8 = 0x35; // Default
+        self.begin_settling();
 
-        const SCALING_DCWCTR_ADDR: u8 = 0x72;
-        const SCALING_DCWCTR_HORZ_DOWNSAMPLE: u8 = 0x01; // Horizontal downsample by 2
-        const SCALING_DCWCTR_VERT_DOWNSAMPLE: u8 = 0x10; // Vertical downsample by 2
+        Ok(())
+    }
 
-        const SCALING_PCLK_DIV_ADDR: u8 = 0x73;
-        const SCALING_PCLK_DIV_CLOCK_DIVIDER: u8 = 0x01; // Divide by 2
+    fn wait_frame_start(&self) -> Result<(), CaptureError> {
 
-        const SCALING_PCLK_DELAY_ADDR: u8 = 0xA2;
-        const SCALING_PCLK_DELAY_SCALING_OUTPUT_DELAY: u8 = 0x02; // Default
+        // Frames still settling after a mode change are let through unseen,
+        // each with the usual VSYNC timeouts
+        loop {
+            // vsync pulses active before a new frame starts
+            self.wait_vsync(false)?; // wait for vsync to become active
+            self.wait_vsync(true)?; // wait for vsync to end
 
-        const COM15_ADDR: u8 = 0x40;
-        const COM15_DATA_FORMAT: u8 = 0xC0; // Full ([00] to [FF])
-        const COM15_RGB_OPTION: u8 = 0x10; // RGB 565
+            match self.settling.get() {
+                0 => return Ok(()),
+                remaining => self.settling.set(remaining - 1)
+            }
+        }
+    }
 
-        const COM8_ADDR: u8 = 0x13;
-        const COM8_AWB_ENABLE: u8 = 0x02; // Auto white balance
-        const COM8_AEC_ENABLE: u8 = 0x01; // Auto exposure control
+    fn capture_line(&self, buf: &mut [Rgb565]) -> Result<(), CaptureError> {
 
-        const GAIN_ADDR: u8 = 0x00;
-        const GAIN_AGC: u8 = 0xA0; // [00,FF]
+        // wait for href to become active - start of row
+        self.wait_href(false)?;
 
-        // Reset all registers to default values
-        self.sccb_write(COM7_ADDR, COM7_RESET); // COM7: reset
-        asm::delay(CLK_HZ / 1000 * 120); // ~120ms
+        let x = match self.data_bus {
+            DataBus::Contiguous { port, offset } => self.sample_line_fast(buf, port, offset)?,
+            DataBus::Pins(_) => self.sample_line(buf)?
+        };
 
-        // Configure OV7670 to use QVGA with downsampling to get 160x120 resolution
-        self.sccb_write(COM7_ADDR, COM7_RGB_SELECT | COM7_QVGA_SELECT);
-        self.sccb_write(CLKRC_ADDR, CLKRC_PRESCALER);
-        self.sccb_write(COM3_ADDR, COM3_DCW_EN);
-        self.sccb_write(COM14_ADDR, COM14_MANUAL_SCALE_EN | COM14_DCW_AND_PCLK_SCALE_EN | COM14_PCLK_DIVIDER);
-        self.sccb_write(SCALING_XSC_ADDR, SCALING_XSC_HORZ_SCALE_FACTOR);
-        self.sccb_write(SCALING_YSC_ADDR, SCALING_YSC_VERT_SCALE_FACTOR);
-        self.sccb_write(SCALING_DCWCTR_ADDR, SCALING_DCWCTR_HORZ_DOWNSAMPLE | SCALING_DCWCTR_VERT_DOWNSAMPLE);
-        self.sccb_write(SCALING_PCLK_DIV_ADDR, SCALING_PCLK_DIV_CLOCK_DIVIDER);
-        self.sccb_write(SCALING_PCLK_DELAY_ADDR, SCALING_PCLK_DELAY_SCALING_OUTPUT_DELAY);
-        self.sccb_write(COM15_ADDR, COM15_DATA_FORMAT | COM15_RGB_OPTION);
+        // Software R/B swap, done after the line so the sampling loop stays tight
+        if self.swap_rb.get() {
+            for pixel in buf.iter_mut().take(x) {
+                *pixel = pixel.swap_rb();
+            }
+        }
 
-        // Apply additionaly tuning to improve image quality
-        self.sccb_write(COM8_ADDR, COM8_AWB_ENABLE | COM8_AEC_ENABLE);
-        self.sccb_write(GAIN_ADDR, GAIN_AGC);
+        Ok(())
     }
 
-    fn draw_frame(&self, display: &ST7735) {
+    #[cfg(feature = "display")]
+    fn draw_frame(&self, display: &ST7735) -> Result<(), CaptureError> {
+        self.draw_frame_sized::<{ OV7670::FRAME_WIDTH }>(display)
+    }
 
-        // vsync pulses high before a new frame starts
-        while !self.read_vsync() {} // wait for vsync rising edge
-        while self.read_vsync() {} // wait for vsync falling edge
+    #[cfg(feature = "display")]
+    fn draw_frame_scaled(&self, display: &ST7735, dst_w: u32, dst_h: u32) -> Result<(), CaptureError> {
 
-        // RGB 565 buffer
-        let mut buf: [u16; 160] = [0; 160];
+        let dst_w = dst_w.min(OV7670::FRAME_WIDTH as u32);
 
-        // TODO: dynamically parse rows
-        for y in 0..80 {
-            let mut x = 0;
+        if dst_w == 0 || dst_h == 0 {
+            return Ok(());
+        }
 
-            // wait for an hsync rising edge - start of row
-            while !self.read_hsync() {};
+        // 16.16 fixed-point source steps per destination pixel/row
+        let step_x = ((OV7670::FRAME_WIDTH as u32) << 16) / dst_w;
+        let src_h = self.frame_rows() as u32;
+        let step_y = (src_h << 16) / dst_h;
 
-            while self.read_hsync() {
+        self.wait_frame_start()?;
 
-                // wait for pclk rising edge
-                while !self.read_pclk() {}
+        let mut src = [Rgb565::BLACK; OV7670::FRAME_WIDTH];
+        let mut dst = [Rgb565::BLACK; OV7670::FRAME_WIDTH];
 
-                let data_msb: u8 = self.read_data();
+        let mut dst_y = 0;
+        let mut next_src_y = 0; // 16.16 source row that feeds `dst_y`
 
-                // wait for pclk falling edge
-                while self.read_pclk() {}
+        for src_y in 0..src_h {
 
-                // wait for pclk rising edge
-                while !self.read_pclk() {}
+            if dst_y >= dst_h {
+                break;
+            }
 
-                let data_lsb: u8 = self.read_data();
+            self.capture_line(&mut src)?;
 
-                // Concat data MSB and LSB
-                let data: u16 = ((data_msb as u16) << 8) | (data_lsb as u16);
+            // Emit every destination row that samples this source row
+            while dst_y < dst_h && next_src_y >> 16 == src_y {
 
-                if x < 160 {
-                    buf[x] = data;
+                let mut src_x = 0;
+                for pixel in dst.iter_mut().take(dst_w as usize) {
+                    *pixel = src[(src_x >> 16) as usize];
+                    src_x += step_x;
                 }
 
-                x += 1;
+                display.draw_row(dst_y, &dst[..dst_w as usize]);
 
-                while self.read_pclk() {} // wait for pclk falling edge
+                dst_y += 1;
+                next_src_y += step_y;
             }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "display")]
+    fn run(&self, display: &ST7735, on_frame: &mut impl FnMut()) -> CaptureError {
+        loop {
+            let frame = self.wait_frame_start().and_then(|()| {
+                on_frame();
+                self.draw_lines::<{ OV7670::FRAME_WIDTH }>(display)
+            });
 
-            display.draw_row(y, &buf);
+            if let Err(error) = frame {
+                return error;
+            }
         }
     }
 }
 
 impl<'a> OV7670<'a> {
 
-    const HSI_HZ: usize = 16_000_000;
-    const SCL_HZ: usize = 100_000;
-
     const I2C_ADDR: u8 = 0x21;
 
+    /// Output resolution (QVGA downsampled by 2)
+    pub const FRAME_WIDTH: usize = 160;
+    pub const FRAME_HEIGHT: usize = 120;
+
+    /// Register table applied by `calibrate` after a reset
+    ///
+    /// QVGA RGB 565 with AWB/AEC. `calibrate` then downsamples by 2 to
+    /// 160x120 with `set_scaling` and loads `DEFAULT_COLOR_MATRIX`.
+    /// Alternate modes can be applied the same way with `write_registers`
+    /// after `soft_reset`.
+    pub const DEFAULT_REGISTERS: &'static [(u8, u8)] = &[
+        (0x12, 0x14), // COM7: QVGA, RGB
+        (0x11, 0x01), // CLKRC: CLK = CLK_IN / 2
+        (0x0C, 0x04), // COM3: DCW enable
+        (0x70, 0x3A), // SCALING_XSC: default horizontal scale factor
+        (0x71, 0x35), // SCALING_YSC: default vertical scale factor
+        (0xA2, 0x02), // SCALING_PCLK_DELAY: default scaling output delay
+        (0x40, 0xD0), // COM15: full [00] to [FF] range, RGB 565
+        (0x13, 0x03), // COM8: AWB, AEC
+        (0x00, 0xA0)  // GAIN: AGC
+    ];
+
+    /// RGB 565 tuned color matrix applied by `calibrate`, as
+    /// `set_color_matrix` arguments; the reset default leaves a green cast
+    pub const DEFAULT_COLOR_MATRIX: ([u8; 6], u8) = (
+        [0xB3, 0xB3, 0x00, 0x3D, 0xA7, 0xE4], // MTX1-MTX6
+        0x1E // MTX2-MTX5 negative
+    );
+
+    // Pins the driver and debug USART use, unavailable to the data bus:
+    // SCCB (PB8/PB9), VSYNC/HREF/PCLK (PA6/PB3/PA9), XCLK on MCO1 (PA8) and
+    // USART2 (PA2/PA3)
+    const RESERVED_PINS: [(Port, u8); 8] = [
+        (Port::B, 8), (Port::B, 9),
+        (Port::A, 6), (Port::B, 3), (Port::A, 9),
+        (Port::A, 8),
+        (Port::A, 2), (Port::A, 3)
+    ];
+
+    // Display SPI1 SCK/SDA (PA5/PA7), unavailable to the power pins
+    const DISPLAY_SPI_PINS: [(Port, u8); 2] = [(Port::A, 5), (Port::A, 7)];
+
+    // Highest rated internal clock
+    const MAX_INTERNAL_HZ: u32 = 24_000_000;
+
+    // Capture wait budgets in core cycles
+    const VSYNC_TIMEOUT: u32 = CLK_HZ; // ~1s, longer than a frame at any supported rate
+    const HREF_TIMEOUT: u32 = CLK_HZ / 100; // ~10ms, covers vertical blanking
+    const PCLK_TIMEOUT: u32 = CLK_HZ / 1000; // ~1ms
+
+    // Budget for clocks and peripherals to come up in `new`
+    const INIT_TIMEOUT: u32 = CLK_HZ / 100; // ~10ms
+
     pub fn new(
-        rcc: &stm32f401::RCC,
+        rcc: &'a stm32f401::RCC,
         gpioa: &'a stm32f401::GPIOA,
         gpiob: &'a stm32f401::GPIOB,
         gpioc: &'a stm32f401::GPIOC,
-        i2c1: stm32f401::I2C1
-    ) -> Self {
+        i2c1: stm32f401::I2C1,
+        data_bus: DataBus,
+        config: &Config
+    ) -> Result<Self, InitError> {
 
         // Enable GPIOA, GPIOB, GPIOC clocks
         rcc.ahb1enr.modify(|_, w| {
@@ -203,21 +545,27 @@ impl<'a> OV7670<'a> {
         gpiob.afrh.modify(|_, w| w.afrh9().af4());
 
         // Configure data pins (GPIO)
-        gpioc.moder.modify(|_, w| {
-            w.moder0().input()
-             .moder1().input()
-             .moder2().input()
-             .moder3().input()
-             .moder4().input()
-             .moder5().input()
-             .moder6().input()
-             .moder7().input()
-        });
+        // Data bus must fit within a 16-pin port
+        if let DataBus::Contiguous { offset, .. } = data_bus {
+            if offset > 8 {
+                return Err(InitError::InvalidConfig);
+            }
+        }
+
+        let data_pins = data_bus.pins();
+
+        if data_pins.iter().any(|&(port, pin)| pin >= 16 || OV7670::RESERVED_PINS.contains(&(port, pin))) {
+            return Err(InitError::InvalidConfig);
+        }
+
+        for (port, pin) in data_pins {
+            OV7670::configure_input(gpioa, gpiob, gpioc, port, pin);
+        }
 
         // Configure VSYNC (GPIO)
         gpioa.moder.modify(|_, w| w.moder6().input());
 
-        // Configure HSYNC (GPIO)
+        // Configure HREF (GPIO)
         gpiob.moder.modify(|_, w| w.moder3().input());
 
         // Configure PCLK (GPIO)
@@ -228,175 +576,1526 @@ impl<'a> OV7670<'a> {
         gpioa.afrh.modify(|_, w| w.afrh8().af0());
 
         // Enable HSI (16 MHz clock)
-        rcc.cr.modify(|_, w| w.hsion().on());
-        while rcc.cr.read().hsirdy().is_not_ready() {}
+        let hsi_ready = retry_with_backoff(OV7670::INIT_TIMEOUT, INIT_ATTEMPTS, |budget| {
+            rcc.cr.modify(|_, w| w.hsion().on());
+            wait_while(budget, || rcc.cr.read().hsirdy().is_not_ready())
+        });
+
+        if !hsi_ready {
+            return Err(InitError::ClockTimeout);
+        }
 
         // Select HSI as XCLK source
         rcc.cfgr.modify(|_, w| {
-            w.mco1().hsi()
-             .mco1pre().div1()
+            let w = w.mco1().hsi();
+            match config.xclk_divider {
+                2 => w.mco1pre().div2(),
+                3 => w.mco1pre().div3(),
+                4 => w.mco1pre().div4(),
+                5 => w.mco1pre().div5(),
+                _ => w.mco1pre().div1()
+            }
         });
 
         // Enable I2C1 clock
         rcc.apb1enr.modify(|_, w| w.i2c1en().enabled());
 
-        // Specify I2C1 input clock frequency for timing
-        i2c1.cr2.modify(|_, w| unsafe { w.freq().bits((OV7670::HSI_HZ / 1_000_000) as u8) });
-
-        // CCR = CLK / (2 × SCL)
-        const CCR: usize = OV7670::HSI_HZ / (2 * OV7670::SCL_HZ);
+        // APB1 runs undivided off the core clock
+        OV7670::init_i2c(&i2c1, CLK_HZ, config)?;
 
-        // Configure I2C1_SCL in standard mode (100KHz)
-        i2c1.ccr.modify(|_, w| unsafe {
-            w.f_s().clear_bit();
-            w.ccr().bits(CCR as u16)
+        let i2c_enabled = retry_with_backoff(OV7670::INIT_TIMEOUT, INIT_ATTEMPTS, |budget| {
+            i2c1.cr1.modify(|_, w| w.pe().enabled());
+            wait_while(budget, || i2c1.cr1.read().pe().is_disabled())
         });
 
-        // trise = CLK[MHz] + 1 (standard mode)
-        const TRISE: usize = OV7670::HSI_HZ / 1_000_000 + 1;
+        if !i2c_enabled {
+            return Err(InitError::EnableTimeout);
+        }
+
+        Ok(OV7670 {
+            rcc,
+            gpioa,
+            gpiob,
+            gpioc,
+            i2c1,
+            data_bus,
+            power_pins: PowerPins::default(),
+            config: *config,
+            apb1_hz: Cell::new(CLK_HZ),
+            pclk_inverted: Cell::new(false),
+            href_inverted: Cell::new(false),
+            vsync_inverted: Cell::new(false),
+            #[cfg(feature = "display")]
+            debug_overlay: Cell::new(false),
+            blend: Cell::new(0),
+            settle_frames: Cell::new(0),
+            settling: Cell::new(0),
+            frame_height: Cell::new(OV7670::FRAME_HEIGHT as u32),
+            stream_config: Cell::new(StreamConfig { crc: false }),
+            swap_rb: Cell::new(false)
+        })
+    }
 
-        // Configure I2C rise time
-        i2c1.trise.modify(|_, w|
-            w.trise().bits(TRISE as u8)
-        );
+    /// Reset all registers to their default values
+    ///
+    /// Blocks for ~120ms while the sensor settles. Registers must be
+    /// reconfigured (e.g. with `calibrate`) before capturing again.
+    pub fn soft_reset(&self, delay: &mut impl DelayMs) -> Result<(), I2cError> {
 
-        // Enable I2C1
-        i2c1.cr1.modify(|_, w| w.pe().enabled());
+        const COM7_ADDR: u8 = 0x12;
+        const COM7_RESET: u8 = 0x80;
+
+        self.sccb_write(COM7_ADDR, COM7_RESET)?;
+        delay.delay_ms(120);
 
-        OV7670 { gpioa, gpiob, gpioc, i2c1 }
+        // Registers are back to defaults, keep the capture settings in sync
+        self.pclk_inverted.set(false);
+        self.href_inverted.set(false);
+        self.vsync_inverted.set(false);
+
+        Ok(())
     }
 
-    // Restore I2C bus to IDLE state
-    #[allow(dead_code)]
-    fn flush_i2c_bus(&self) {
+    /// Take control of the RESET and PWDN lines wired in `power_pins`
+    ///
+    /// Lines are configured as outputs with the sensor released from reset
+    /// and powered up. Fails with `InvalidConfig` if a line is already in
+    /// use (see `PowerPins`).
+    pub fn with_power_pins(mut self, power_pins: PowerPins) -> Result<Self, InitError> {
 
-        // Re-configure SCL and SDA as outputs
-        self.gpiob.moder.modify(|_, w| {
-            w.moder8().output()
-             .moder9().output()
-        });
+        let data_pins = self.data_bus.pins();
 
-        // Attempt to put the bus into the IDLE state (SCL & SDA high)
-        self.gpiob.bsrr.write(|w| {
-            w.bs8().set_bit()
-             .bs9().set_bit()
-        });
+        let taken = |pin: &(Port, u8)| pin.1 >= 16
+            || OV7670::RESERVED_PINS.contains(pin)
+            || OV7670::DISPLAY_SPI_PINS.contains(pin)
+            || data_pins.contains(pin);
 
-        // Manually flush the bus if OV7670 is still driving SDA low
-        for _ in 0..9 {
+        if power_pins.reset.iter().chain(&power_pins.pwdn).any(taken)
+            || (power_pins.reset.is_some() && power_pins.reset == power_pins.pwdn) {
+            return Err(InitError::InvalidConfig);
+        }
 
-            // If SDA is high, the bus is flushed
-            if self.gpiob.idr.read().idr9().bit_is_set() {
-                break;
+        for (line, active) in [(power_pins.reset, false), (power_pins.pwdn, true)] {
+            if let Some((port, pin)) = line {
+                OV7670::write_output(self.gpioa, self.gpiob, self.gpioc, port, pin, !active);
+                OV7670::configure_output(self.gpioa, self.gpiob, self.gpioc, port, pin);
             }
+        }
 
-            self.gpiob.bsrr.write(|w| w.br8().set_bit()); // SCL low
-            asm::delay(1000);
+        self.power_pins = power_pins;
 
-            self.gpiob.bsrr.write(|w| w.bs8().set_bit()); // SCL high
-            asm::delay(1000);
+        Ok(self)
+    }
+
+    /// Pulse the RESET line, returning every register to its default
+    ///
+    /// Recovers a sensor that no longer answers on SCCB, where `soft_reset`
+    /// can't get through. Blocks for ~120ms; reconfigure with `calibrate`
+    /// afterwards. Returns false, doing nothing, without a RESET pin (see
+    /// `with_power_pins`).
+    pub fn hardware_reset(&self, delay: &mut impl DelayMs) -> bool {
+
+        let Some((port, pin)) = self.power_pins.reset else {
+            return false;
+        };
+
+        OV7670::write_output(self.gpioa, self.gpiob, self.gpioc, port, pin, false);
+        delay.delay_ms(1); // Datasheet minimum pulse width
+        OV7670::write_output(self.gpioa, self.gpiob, self.gpioc, port, pin, true);
+        delay.delay_ms(120);
+
+        // Registers are back to defaults, keep the capture settings in sync
+        self.pclk_inverted.set(false);
+        self.href_inverted.set(false);
+        self.vsync_inverted.set(false);
+
+        true
+    }
+
+    /// Put the sensor into (or bring it out of) power down
+    ///
+    /// Registers are kept while powered down, but the first few frames after
+    /// waking are unstable while exposure settles. Does nothing without a
+    /// PWDN pin, see `with_power_pins`.
+    pub fn power_down(&self, on: bool) {
+        if let Some((port, pin)) = self.power_pins.pwdn {
+            OV7670::write_output(self.gpioa, self.gpiob, self.gpioc, port, pin, on);
         }
+    }
 
-        // Generate a manual stop signal (SDA rises while SCL is high)
-        self.gpiob.bsrr.write(|w| w.br9().set_bit()); // SDA low
-        asm::delay(1000);
-        self.gpiob.bsrr.write(|w| w.bs8().set_bit()); // SCL high
-        asm::delay(1000);
-        self.gpiob.bsrr.write(|w| w.bs9().set_bit()); // SDA high
-        asm::delay(1000);
+    /// Write a table of `(address, value)` register pairs in order
+    ///
+    /// Stops at the first failed write.
+    pub fn write_registers(&self, regs: &[(u8, u8)]) -> Result<(), I2cError> {
+        regs.iter().try_for_each(|&(addr, value)| self.sccb_write(addr, value))
+    }
 
-        // Restore SCL as I2C1_SCL
-        self.gpiob.moder.modify(|_, w| w.moder8().alternate());
-        self.gpiob.afrh.modify(|_, w| w.afrh8().af4());
+    /// Downsample the QVGA output by `h_down` horizontally and `v_down`
+    /// vertically
+    ///
+    /// Factors are rounded down to 1, 2, 4 or 8. The DCW downsampler, the
+    /// scaling PCLK divider and COM14's PCLK divider are programmed together
+    /// so PCLK slows with the horizontal factor. Returns the output
+    /// dimensions, e.g. (80, 60) for /4.
+    pub fn set_scaling(&self, h_down: u8, v_down: u8) -> Result<(usize, usize), I2cError> {
 
-        // Restore SDA as I2C1_SDA
-        self.gpiob.moder.modify(|_, w| w.moder9().alternate());
-        self.gpiob.afrh.modify(|_, w| w.afrh9().af4());
+        const COM14_ADDR: u8 = 0x3E;
+        const COM14_MANUAL_SCALE_EN: u8 = 0x08;
+        const COM14_DCW_AND_PCLK_SCALE_EN: u8 = 0x10;
+
+        const SCALING_DCWCTR_ADDR: u8 = 0x72; // Vertical [5:4], horizontal [1:0]
+        const SCALING_PCLK_DIV_ADDR: u8 = 0x73;
+
+        // Output before downsampling
+        const QVGA_WIDTH: usize = 320;
+        const QVGA_HEIGHT: usize = 240;
+
+        // Registers encode the factor as log2
+        let h = h_down.max(1).ilog2().min(3) as u8;
+        let v = v_down.max(1).ilog2().min(3) as u8;
+
+        self.sccb_write(COM14_ADDR, COM14_MANUAL_SCALE_EN | COM14_DCW_AND_PCLK_SCALE_EN | h)?;
+        self.sccb_write(SCALING_DCWCTR_ADDR, (v << 4) | h)?;
+        self.sccb_write(SCALING_PCLK_DIV_ADDR, h)?;
+
+        self.frame_height.set((QVGA_HEIGHT >> v) as u32);
+
+        self.begin_settling();
+
+        Ok((QVGA_WIDTH >> h, QVGA_HEIGHT >> v))
     }
 
-    // Issue a register read on the OV7670
-    fn sccb_read(&self, addr: u8) -> u8 {
+    /// Approximate `target_fps` by programming the internal clock tree
+    ///
+    /// The internal clock is XCLK × DBLV PLL multiplier / (CLKRC prescaler +
+    /// 1), and a frame takes 784×510 pixels of 2 clocks (30fps at 24MHz).
+    /// Picks the closest combination without exceeding a 24MHz internal
+    /// clock and returns the achieved rate, rounded down.
+    pub fn set_framerate(&self, target_fps: u8) -> Result<u8, I2cError> {
 
-        const READ: u8 = 0x1;
-        const WRITE: u8 = 0x0;
+        const CLKRC_ADDR: u8 = 0x11;
+        const CLKRC_PRESCALER: u8 = 0x3F;
 
-        // Send start signal
-        self.i2c1.cr1.modify(|_, w| w.start().set_bit());
-        while self.i2c1.sr1.read().sb().bit_is_clear() {}
+        const DBLV_ADDR: u8 = 0x6B;
+        const DBLV_PLL: u8 = 0xC0;
 
-        // Enable OV7670 write mode
-        self.i2c1.dr.write(|w| w.dr().bits((OV7670::I2C_ADDR << 1) | WRITE));
-        while self.i2c1.sr1.read().addr().bit_is_clear() {}
-        self.i2c1.sr2.read().bits(); // Read to clear addr sent flag
+        const FRAME_CLOCKS: u32 = 784 * 510 * 2;
 
-        // Write the register address to the bus
-        self.i2c1.dr.write(|w| w.dr().bits(addr));
-        while self.i2c1.sr1.read().btf().bit_is_clear() {}
+        let xclk = CLK_HZ / self.config.xclk_divider;
+        let target = target_fps as u32 * FRAME_CLOCKS;
 
-        // Send stop signal
-        self.i2c1.cr1.modify(|_, w| w.stop().set_bit());
+        // (internal clock, PLL, prescaler) closest to the target
+        let mut best = (xclk, PllMult::Bypass, 0);
 
-        // Send start signal
-        self.i2c1.cr1.modify(|_, w| w.start().set_bit());
-        while self.i2c1.sr1.read().sb().bit_is_clear() {}
+        for pll in PllMult::ALL {
+            for prescaler in 0..=CLKRC_PRESCALER {
 
-        // Enable OV7670 read mode
-        self.i2c1.dr.write(|w| w.dr().bits((OV7670::I2C_ADDR << 1) | READ));
-        while self.i2c1.sr1.read().addr().bit_is_clear() {}
-        self.i2c1.sr2.read().bits(); // Read to clear addr sent flag
+                let internal = xclk * pll.multiplier() / (prescaler as u32 + 1);
 
-        // NACK next byte, send stop signal
-        self.i2c1.cr1.modify(|_, w| {
-            w.ack().clear_bit()
-             .stop().set_bit()
-        });
+                if internal <= OV7670::MAX_INTERNAL_HZ && internal.abs_diff(target) < best.0.abs_diff(target) {
+                    best = (internal, pll, prescaler);
+                }
+            }
+        }
+
+        let (internal, pll, prescaler) = best;
 
-        // Wait for data to be ready
-        while self.i2c1.sr1.read().rx_ne().bit_is_clear() {}
+        self.sccb_modify(DBLV_ADDR, DBLV_PLL, pll.bits())?;
+        self.sccb_modify(CLKRC_ADDR, CLKRC_PRESCALER, prescaler)?;
 
-        // Read data
-        self.i2c1.dr.read().dr().bits()
+        self.begin_settling();
+
+        Ok((internal / FRAME_CLOCKS).min(u8::MAX as u32) as u8)
     }
 
-    // Issue a register write on the OV7670
-    fn sccb_write(&self, addr: u8, data: u8) {
+    /// Read back the current frame rate from the clock tree, rounded down
+    ///
+    /// Uses the same frame timing as `set_framerate`.
+    pub fn framerate(&self) -> Result<u8, I2cError> {
+
+        const CLKRC_ADDR: u8 = 0x11;
+        const CLKRC_PRESCALER: u8 = 0x3F;
 
-        const WRITE: u8 = 0x0;
+        const DBLV_ADDR: u8 = 0x6B;
+        const DBLV_PLL: u8 = 0xC0;
 
-        // Send start signal
-        self.i2c1.cr1.modify(|_, w| w.start().set_bit());
-        while self.i2c1.sr1.read().sb().bit_is_clear() {}
+        const FRAME_CLOCKS: u32 = 784 * 510 * 2;
 
-        // Enable OV7670 write mode
-        self.i2c1.dr.write(|w| w.dr().bits((OV7670::I2C_ADDR << 1) | WRITE));
-        while self.i2c1.sr1.read().addr().bit_is_clear() {}
-        self.i2c1.sr2.read().bits(); // Read to clear addr sent flag
+        let prescaler = (self.sccb_read(CLKRC_ADDR)? & CLKRC_PRESCALER) as u32;
+        let pll_bits = self.sccb_read(DBLV_ADDR)? & DBLV_PLL;
 
-        // Write the register address to the bus
-        self.i2c1.dr.write(|w| w.dr().bits(addr));
-        while self.i2c1.sr1.read().btf().bit_is_clear() {}
+        let pll = PllMult::ALL.into_iter()
+            .find(|pll| pll.bits() == pll_bits)
+            .unwrap_or(PllMult::Bypass);
 
-        // Write the data to the bus
-        self.i2c1.dr.write(|w| w.dr().bits(data));
-        while self.i2c1.sr1.read().btf().bit_is_clear() {}
+        let internal = CLK_HZ / self.config.xclk_divider * pll.multiplier() / (prescaler + 1);
 
-        // Send stop signal
-        self.i2c1.cr1.modify(|_, w| w.stop().set_bit());
+        Ok((internal / FRAME_CLOCKS).min(u8::MAX as u32) as u8)
     }
 
-    fn read_vsync(&self) -> bool {
-        self.gpioa.idr.read().idr6().bit()
+    /// Set the PLL multiplier applied to XCLK, returning the resulting
+    /// internal clock in Hz
+    ///
+    /// The internal clock is XCLK × PLL / (CLKRC prescaler + 1), so the PLL
+    /// raises the frame rate (and PCLK) without a faster MCO. The current
+    /// CLKRC prescaler is kept, so check the returned clock: above 24MHz the
+    /// sensor is out of its rating and PCLK may outrun the capture loop.
+    /// `set_framerate` picks the PLL and prescaler together and overrides
+    /// this.
+    pub fn set_pll(&self, mult: PllMult) -> Result<u32, I2cError> {
+
+        const CLKRC_ADDR: u8 = 0x11;
+        const CLKRC_PRESCALER: u8 = 0x3F;
+
+        const DBLV_ADDR: u8 = 0x6B;
+        const DBLV_PLL: u8 = 0xC0;
+
+        let prescaler = (self.sccb_read(CLKRC_ADDR)? & CLKRC_PRESCALER) as u32;
+        let internal = CLK_HZ / self.config.xclk_divider * mult.multiplier() / (prescaler + 1);
+
+        self.sccb_modify(DBLV_ADDR, DBLV_PLL, mult.bits())?;
+
+        self.begin_settling();
+
+        Ok(internal)
     }
 
-    fn read_hsync(&self) -> bool {
-        self.gpiob.idr.read().idr3().bit()
+    /// Mirror the image horizontally
+    pub fn set_mirror(&self, on: bool) -> Result<(), I2cError> {
+
+        const MVFP_ADDR: u8 = 0x1E;
+        const MVFP_MIRROR: u8 = 0x20;
+
+        self.sccb_modify(MVFP_ADDR, MVFP_MIRROR, if on { MVFP_MIRROR } else { 0 })?;
+
+        Ok(())
     }
 
-    fn read_pclk(&self) -> bool {
-        self.gpioa.idr.read().idr9().bit()
+    /// Enable or disable automatic exposure control (AEC)
+    pub fn set_auto_exposure(&self, on: bool) -> Result<(), I2cError> {
+
+        const COM8_ADDR: u8 = 0x13;
+        const COM8_AEC_ENABLE: u8 = 0x01;
+
+        self.sccb_modify(COM8_ADDR, COM8_AEC_ENABLE, if on { COM8_AEC_ENABLE } else { 0 })?;
+
+        Ok(())
     }
 
-    fn read_data(&self) -> u8 {
-        self.gpioc.idr.read().bits() as u8
+    /// Set a manual 16-bit exposure value, disabling AEC
+    ///
+    /// Exposure is in units of row intervals. The value is split across
+    /// AECHH[5:0] (bits 15:10), AECH (bits 9:2) and COM1[1:0] (bits 1:0).
+    pub fn set_exposure(&self, value: u16) -> Result<(), I2cError> {
+
+        const AECHH_ADDR: u8 = 0x07;
+        const AECHH_MASK: u8 = 0x3F;
+
+        const AECH_ADDR: u8 = 0x10;
+
+        const COM1_ADDR: u8 = 0x04;
+        const COM1_AEC_MASK: u8 = 0x03;
+
+        self.set_auto_exposure(false)?;
+
+        self.sccb_modify(AECHH_ADDR, AECHH_MASK, (value >> 10) as u8)?;
+        self.sccb_write(AECH_ADDR, (value >> 2) as u8)?;
+        self.sccb_modify(COM1_ADDR, COM1_AEC_MASK, value as u8)?;
+
+        Ok(())
+    }
+
+    /// Set a manual analog gain, disabling automatic gain control (AGC)
+    pub fn set_gain(&self, gain: u8) -> Result<(), I2cError> {
+
+        const COM8_ADDR: u8 = 0x13;
+        const COM8_AGC_ENABLE: u8 = 0x04;
+
+        const GAIN_ADDR: u8 = 0x00;
+
+        self.sccb_modify(COM8_ADDR, COM8_AGC_ENABLE, 0)?;
+        self.sccb_write(GAIN_ADDR, gain)?;
+
+        Ok(())
+    }
+
+    /// Read back the current 16-bit exposure, as set by AEC or `set_exposure`
+    pub fn read_exposure(&self) -> Result<u16, I2cError> {
+
+        const AECHH_ADDR: u8 = 0x07;
+        const AECH_ADDR: u8 = 0x10;
+        const COM1_ADDR: u8 = 0x04;
+
+        let high = (self.sccb_read(AECHH_ADDR)? & 0x3F) as u16;
+        let mid = self.sccb_read(AECH_ADDR)? as u16;
+        let low = (self.sccb_read(COM1_ADDR)? & 0x03) as u16;
+
+        Ok((high << 10) | (mid << 2) | low)
+    }
+
+    /// Read back the current 10-bit analog gain, as set by AGC or `set_gain`
+    ///
+    /// GAIN holds bits 7:0 and VREF[7:6] holds bits 9:8.
+    pub fn read_gain(&self) -> Result<u16, I2cError> {
+
+        const GAIN_ADDR: u8 = 0x00;
+        const VREF_ADDR: u8 = 0x03;
+
+        let low = self.sccb_read(GAIN_ADDR)? as u16;
+        let high = (self.sccb_read(VREF_ADDR)? >> 6) as u16;
+
+        Ok((high << 8) | low)
+    }
+
+    /// Set the automatic gain ceiling
+    ///
+    /// Higher ceilings brighten low-light scenes at the cost of noise.
+    pub fn set_agc_ceiling(&self, ceiling: AgcCeiling) -> Result<(), I2cError> {
+
+        const COM9_ADDR: u8 = 0x14;
+        const COM9_CEILING_MASK: u8 = 0x70;
+
+        self.sccb_modify(COM9_ADDR, COM9_CEILING_MASK, (ceiling as u8) << 4)
+    }
+
+    /// Select the AEC metering algorithm and whether fast AEC is allowed
+    ///
+    /// Fast AEC (COM8[7]) takes large exposure steps when the scene is
+    /// outside the fast-mode zone set by `set_aec_window`.
+    pub fn set_aec_algorithm(&self, algorithm: AecAlgorithm, fast: bool) -> Result<(), I2cError> {
+
+        const COM8_ADDR: u8 = 0x13;
+        const COM8_FAST_AEC: u8 = 0x80;
+
+        const NALG_ADDR: u8 = 0xAA;
+        const NALG_HISTOGRAM: u8 = 0x80;
+
+        let histogram = match algorithm {
+            AecAlgorithm::Average => 0,
+            AecAlgorithm::Histogram => NALG_HISTOGRAM
+        };
+
+        self.sccb_modify(NALG_ADDR, NALG_HISTOGRAM, histogram)?;
+        self.sccb_modify(COM8_ADDR, COM8_FAST_AEC, if fast { COM8_FAST_AEC } else { 0 })
+    }
+
+    /// Set the AEC stable operating window
+    ///
+    /// AEC stops adjusting once the measured luminance is between `lower`
+    /// (AEB) and `upper` (AEW). `fast_zone` (VPT) packs the high/low
+    /// thresholds in its upper/lower nibbles, outside of which fast AEC kicks in.
+    pub fn set_aec_window(&self, upper: u8, lower: u8, fast_zone: u8) -> Result<(), I2cError> {
+
+        const AEW_ADDR: u8 = 0x24;
+        const AEB_ADDR: u8 = 0x25;
+        const VPT_ADDR: u8 = 0x26;
+
+        self.sccb_write(AEW_ADDR, upper)?;
+        self.sccb_write(AEB_ADDR, lower)?;
+        self.sccb_write(VPT_ADDR, fast_zone)
+    }
+
+    /// Enable or disable automatic white balance (AWB)
+    pub fn set_awb(&self, on: bool) -> Result<(), I2cError> {
+
+        const COM8_ADDR: u8 = 0x13;
+        const COM8_AWB_ENABLE: u8 = 0x02;
+
+        self.sccb_modify(COM8_ADDR, COM8_AWB_ENABLE, if on { COM8_AWB_ENABLE } else { 0 })?;
+
+        Ok(())
+    }
+
+    /// Enable or disable automatic black level calibration (ABLC)
+    ///
+    /// ABLC tracks the sensor's optical black rows and subtracts their level,
+    /// which removes the milky floor from dark areas. Enabling also sets the
+    /// black level target (THL_ST) to the value OmniVision recommends.
+    pub fn set_black_level_calibration(&self, on: bool) -> Result<(), I2cError> {
+
+        const ABLC1_ADDR: u8 = 0xB1;
+        const ABLC1_ENABLE: u8 = 0x04;
+
+        const THL_ST_ADDR: u8 = 0xB3;
+        const THL_ST_TARGET: u8 = 0x82;
+
+        if on {
+            self.sccb_write(THL_ST_ADDR, THL_ST_TARGET)?;
+        }
+
+        self.sccb_modify(ABLC1_ADDR, ABLC1_ENABLE, if on { ABLC1_ENABLE } else { 0 })
+    }
+
+    /// Restart black level calibration, e.g. after a large temperature or
+    /// exposure change
+    ///
+    /// There's no trigger register, so ABLC is switched off and back on,
+    /// which makes it converge again from the next frame.
+    pub fn calibrate_black_level(&self) -> Result<(), I2cError> {
+        self.set_black_level_calibration(false)?;
+        self.set_black_level_calibration(true)
+    }
+
+    /// Set manual per-channel white balance gains, disabling AWB
+    pub fn set_wb_gains(&self, red: u8, green: u8, blue: u8) -> Result<(), I2cError> {
+
+        const BLUE_ADDR: u8 = 0x01;
+        const RED_ADDR: u8 = 0x02;
+        const GGAIN_ADDR: u8 = 0x6A;
+
+        // AWB would otherwise overwrite the gains on the next frame
+        self.set_awb(false)?;
+
+        self.sccb_write(BLUE_ADDR, blue)?;
+        self.sccb_write(RED_ADDR, red)?;
+        self.sccb_write(GGAIN_ADDR, green)?;
+
+        Ok(())
+    }
+
+    /// Set the edge enhancement (sharpening) factor
+    ///
+    /// Valid levels are 0 (off) to 31; higher values are clamped. COM16's
+    /// automatic edge threshold is disabled so the manual factor takes effect.
+    pub fn set_sharpness(&self, level: u8) -> Result<(), I2cError> {
+
+        const COM16_ADDR: u8 = 0x41;
+        const COM16_EDGE_AUTO: u8 = 0x20;
+
+        const EDGE_ADDR: u8 = 0x3F;
+        const EDGE_FACTOR_MASK: u8 = 0x1F;
+
+        self.sccb_modify(COM16_ADDR, COM16_EDGE_AUTO, 0)?;
+        self.sccb_modify(EDGE_ADDR, EDGE_FACTOR_MASK, level.min(EDGE_FACTOR_MASK))?;
+
+        Ok(())
+    }
+
+    /// Set the de-noise strength
+    ///
+    /// Valid levels are 0 (off) to 255. COM16's automatic de-noise threshold
+    /// is disabled so DNSTH takes effect, and REG76's black/white pixel
+    /// correction is enabled for any non-zero level.
+    pub fn set_denoise(&self, level: u8) -> Result<(), I2cError> {
+
+        const COM16_ADDR: u8 = 0x41;
+        const COM16_DENOISE_AUTO: u8 = 0x10;
+
+        const DNSTH_ADDR: u8 = 0x4C;
+
+        const REG76_ADDR: u8 = 0x76;
+        const REG76_PIXEL_CORRECTION: u8 = 0xC0; // Black and white pixel correction
+
+        let correction = if level > 0 { REG76_PIXEL_CORRECTION } else { 0 };
+
+        self.sccb_modify(COM16_ADDR, COM16_DENOISE_AUTO, 0)?;
+        self.sccb_write(DNSTH_ADDR, level)?;
+        self.sccb_modify(REG76_ADDR, REG76_PIXEL_CORRECTION, correction)?;
+
+        Ok(())
+    }
+
+    /// Program the color-correction matrix
+    ///
+    /// `coeffs` are the MTX1-MTX6 magnitudes, bit n of `signs` makes
+    /// MTX(n+1) negative (MTXS[5:0]). The auto contrast center bit in MTXS is
+    /// preserved.
+    pub fn set_color_matrix(&self, coeffs: [u8; 6], signs: u8) -> Result<(), I2cError> {
+
+        const MTX1_ADDR: u8 = 0x4F; // MTX1-MTX6 are consecutive
+
+        const MTXS_ADDR: u8 = 0x58;
+        const MTXS_SIGNS: u8 = 0x3F;
+
+        for (addr, coeff) in (MTX1_ADDR..).zip(coeffs) {
+            self.sccb_write(addr, coeff)?;
+        }
+
+        self.sccb_modify(MTXS_ADDR, MTXS_SIGNS, signs)
+    }
+
+    /// Configure lens shading correction
+    ///
+    /// Brightens the frame outside a `radius` around the optical center by
+    /// the per-channel compensation `gains` (red, green, blue). Gated by
+    /// LCC5[0] rather than a COM register; LCC5[2] selects separate R/G/B
+    /// coefficients instead of one shared value.
+    pub fn set_lens_correction(&self, enable: bool, radius: u8, gains: (u8, u8, u8)) -> Result<(), I2cError> {
+
+        const LCC1_ADDR: u8 = 0x62; // Center X offset
+        const LCC2_ADDR: u8 = 0x63; // Center Y offset
+        const LCC3_ADDR: u8 = 0x64; // Green (or shared) coefficient
+        const LCC4_ADDR: u8 = 0x65; // Radius with no compensation
+        const LCC6_ADDR: u8 = 0x94; // Red coefficient
+        const LCC7_ADDR: u8 = 0x95; // Blue coefficient
+
+        const LCC5_ADDR: u8 = 0x66;
+        const LCC5_ENABLE: u8 = 0x01;
+        const LCC5_PER_CHANNEL: u8 = 0x04;
+
+        let (red, green, blue) = gains;
+
+        if enable {
+            self.sccb_write(LCC1_ADDR, 0)?;
+            self.sccb_write(LCC2_ADDR, 0)?;
+            self.sccb_write(LCC4_ADDR, radius)?;
+            self.sccb_write(LCC6_ADDR, red)?;
+            self.sccb_write(LCC3_ADDR, green)?;
+            self.sccb_write(LCC7_ADDR, blue)?;
+        }
+
+        self.sccb_write(LCC5_ADDR, if enable { LCC5_ENABLE | LCC5_PER_CHANNEL } else { 0 })
+    }
+
+    /// Apply a special color effect
+    ///
+    /// Negative sets TSLB[5]. Sepia and black & white set TSLB[4], which
+    /// replaces the chroma (UV) of every pixel with the fixed MANU/MANV
+    /// values, so other tints are a matter of picking different U/V values.
+    pub fn set_effect(&self, effect: ColorEffect) -> Result<(), I2cError> {
+
+        const TSLB_ADDR: u8 = 0x3A;
+        const TSLB_NEGATIVE: u8 = 0x20;
+        const TSLB_FIXED_UV: u8 = 0x10;
+
+        const MANU_ADDR: u8 = 0x67;
+        const MANV_ADDR: u8 = 0x68;
+
+        // (TSLB bits, U, V), U/V only used with TSLB_FIXED_UV
+        let (tslb, u, v) = match effect {
+            ColorEffect::None => (0, 0x80, 0x80),
+            ColorEffect::Negative => (TSLB_NEGATIVE, 0x80, 0x80),
+            ColorEffect::Sepia => (TSLB_FIXED_UV, 0x40, 0xA0),
+            ColorEffect::BlackWhite => (TSLB_FIXED_UV, 0x80, 0x80)
+        };
+
+        self.sccb_write(MANU_ADDR, u)?;
+        self.sccb_write(MANV_ADDR, v)?;
+        self.sccb_modify(TSLB_ADDR, TSLB_NEGATIVE | TSLB_FIXED_UV, tslb)
+    }
+
+    /// `Camera::draw_frame` with a `WIDTH` pixel line buffer
+    ///
+    /// `draw_frame` buffers a full `FRAME_WIDTH` line. Match `WIDTH` to the
+    /// output set with `set_scaling` to save stack (80 for /4) or to keep
+    /// wider lines whole; pixels past `WIDTH` are dropped.
+    #[cfg(feature = "display")]
+    pub fn draw_frame_sized<const WIDTH: usize>(&self, display: &ST7735) -> Result<(), CaptureError> {
+        self.wait_frame_start()?;
+        self.draw_lines::<WIDTH>(display)
+    }
+
+    /// Overlay capture diagnostics on frames drawn by `draw_frame` and `run`
+    ///
+    /// Draws a row of blocks at the top of the panel for the VSYNC, HREF and
+    /// PCLK levels at the end of each frame (green high, red low), followed by
+    /// a bar showing how many of the frame's lines were captured. Frames that
+    /// fail part way still get the overlay.
+    #[cfg(feature = "display")]
+    pub fn set_debug_overlay(&self, on: bool) {
+        self.debug_overlay.set(on);
+    }
+
+    /// Select the channel order of the sensor's RGB 565 output
+    ///
+    /// Programs TSLB[3] (output sequence) together with COM13[0] (UV swap),
+    /// which swap red and blue between them. If a sensor module still comes
+    /// out blue-faced, use `set_software_rb_swap` instead.
+    pub fn set_rgb_order(&self, order: RgbOrder) -> Result<(), I2cError> {
+
+        const TSLB_ADDR: u8 = 0x3A;
+        const TSLB_OUTPUT_SEQUENCE: u8 = 0x08;
+
+        const COM13_ADDR: u8 = 0x3D;
+        const COM13_UV_SWAP: u8 = 0x01;
+
+        let (tslb, com13) = match order {
+            RgbOrder::Rgb => (0, 0),
+            RgbOrder::Bgr => (TSLB_OUTPUT_SEQUENCE, COM13_UV_SWAP)
+        };
+
+        self.sccb_modify(TSLB_ADDR, TSLB_OUTPUT_SEQUENCE, tslb)?;
+        self.sccb_modify(COM13_ADDR, COM13_UV_SWAP, com13)
+    }
+
+    /// Swap red and blue in software on every captured line
+    ///
+    /// Fallback for modules where `set_rgb_order` has no effect. Costs a pass
+    /// over each line during horizontal blanking.
+    pub fn set_software_rb_swap(&self, on: bool) {
+        self.swap_rb.set(on);
+    }
+
+    /// Blend each frame captured by `capture_frame` into the previous one
+    ///
+    /// Every pixel keeps `factor / 256` of what the frame buffer held before,
+    /// leaving motion trails. 0 (default) overwrites the frame, 128 is an even
+    /// 50/50 mix and higher values fade slower. Mixing happens between lines,
+    /// so large frames may start to drop rows (see `CaptureStats`).
+    pub fn set_blend(&self, factor: u8) {
+        self.blend.set(factor);
+    }
+
+    /// Discard `n` frames after a mode change before capturing again
+    ///
+    /// The first frame after `calibrate`, `set_scaling`, `set_framerate` or
+    /// `set_pll` is often torn or exposed with the old settings. With
+    /// settle frames set, the next `n` frames are skipped by every capture
+    /// (`draw_frame`, `capture_frame`, `run`, ...), which costs `n` frame
+    /// times once. 0 (default) captures straight away.
+    pub fn set_settle_frames(&self, n: u8) {
+        self.settle_frames.set(n);
+    }
+
+    /// Select the framing `stream_frame` uses
+    pub fn set_stream_config(&self, config: StreamConfig) {
+        self.stream_config.set(config);
+    }
+
+    /// Invert the pixel clock so data is sampled on its falling edge
+    ///
+    /// Programs COM10's PCLK reverse bit and switches the capture loop to the
+    /// matching edge. A mismatch shows up as byte-swapped garbage.
+    pub fn set_pclk_polarity(&self, inverted: bool) -> Result<(), I2cError> {
+
+        const COM10_ADDR: u8 = 0x15;
+        const COM10_PCLK_REVERSE: u8 = 0x10;
+
+        self.sccb_modify(COM10_ADDR, COM10_PCLK_REVERSE, if inverted { COM10_PCLK_REVERSE } else { 0 })?;
+        self.pclk_inverted.set(inverted);
+
+        Ok(())
+    }
+
+    /// Stop PCLK toggling during horizontal blanking
+    ///
+    /// Programs COM10's PCLK gating bit so every PCLK edge carries a pixel
+    /// byte, required by `DmaCapture`.
+    pub fn set_pclk_gating(&self, gated: bool) -> Result<(), I2cError> {
+
+        const COM10_ADDR: u8 = 0x15;
+        const COM10_PCLK_GATE: u8 = 0x20;
+
+        self.sccb_modify(COM10_ADDR, COM10_PCLK_GATE, if gated { COM10_PCLK_GATE } else { 0 })
+    }
+
+    /// Invert HREF so it is active low
+    ///
+    /// Programs COM10's HREF reverse bit and switches the capture loop to
+    /// treat a low HS pin as data valid.
+    pub fn set_href_polarity(&self, inverted: bool) -> Result<(), I2cError> {
+
+        const COM10_ADDR: u8 = 0x15;
+        const COM10_HREF_REVERSE: u8 = 0x08;
+
+        self.sccb_modify(COM10_ADDR, COM10_HREF_REVERSE, if inverted { COM10_HREF_REVERSE } else { 0 })?;
+        self.href_inverted.set(inverted);
+
+        Ok(())
+    }
+
+    /// Set the active level of VSYNC and HREF
+    ///
+    /// Programs COM10's VSYNC negative and HREF reverse bits and switches the
+    /// capture loop to match, for modules whose sync lines come out inverted.
+    /// The HS pin carries HREF, so `hsync_high` is its data valid level.
+    /// Both are active high after reset.
+    pub fn set_sync_polarity(&self, vsync_high: bool, hsync_high: bool) -> Result<(), I2cError> {
+
+        const COM10_ADDR: u8 = 0x15;
+        const COM10_HREF_REVERSE: u8 = 0x08;
+        const COM10_VSYNC_NEGATIVE: u8 = 0x02;
+
+        let value = (if vsync_high { 0 } else { COM10_VSYNC_NEGATIVE }) | (if hsync_high { 0 } else { COM10_HREF_REVERSE });
+
+        self.sccb_modify(COM10_ADDR, COM10_VSYNC_NEGATIVE | COM10_HREF_REVERSE, value)?;
+        self.vsync_inverted.set(!vsync_high);
+        self.href_inverted.set(!hsync_high);
+
+        Ok(())
+    }
+
+    /// Synchronize to the next frame and iterate over its lines
+    ///
+    /// Each line must be consumed before the camera clocks out the next one,
+    /// so keep per-line processing short.
+    pub fn lines(&self) -> Result<Lines<'_, 'a>, CaptureError> {
+        self.wait_frame_start()?;
+        Ok(Lines { camera: self, remaining: self.frame_rows() })
+    }
+
+    /// Capture the next full frame into `frame`
+    ///
+    /// Returns how many rows were captured and how many started late, which
+    /// happens when per-row work overruns horizontal blanking. With
+    /// `set_blend` the frame is mixed into the previous contents of `frame`.
+    pub fn capture_frame(&self, frame: &mut FrameBuffer) -> Result<CaptureStats, CaptureError> {
+
+        let blend = self.blend.get();
+
+        self.wait_frame_start()?;
+
+        let (mut stats, start) = CaptureStats::start();
+
+        let mut line = [Rgb565::BLACK; FrameBuffer::WIDTH];
+
+        stats.capture_rows(self.frame_rows(), |y| {
+
+            // HREF already active means the start of this line was missed
+            let late = self.read_href();
+
+            // The rows still to come were never sent, the rest of the frame
+            // keeps the previous capture
+            if self.frame_ended_before_line()? {
+                return Ok(None);
+            }
+
+            if blend == 0 {
+                self.capture_line(frame.row_mut(y))?;
+            } else {
+                self.capture_line(&mut line)?;
+                for (pixel, new) in frame.row_mut(y).iter_mut().zip(line) {
+                    *pixel = new.blend(*pixel, blend);
+                }
+            }
+
+            Ok(Some(late))
+        })?;
+
+        Ok(stats.finish(start))
+    }
+
+    /// Capture a frame into `frame` and send it to `out` for viewing on a host
+    ///
+    /// The frame is sent little-endian as:
+    ///
+    /// | Bytes | Field                                             |
+    /// |-------|---------------------------------------------------|
+    /// | 4     | Magic `OVF1`, or `OVFC` with a CRC                |
+    /// | 2     | Width                                             |
+    /// | 2     | Height                                            |
+    /// | W×H×2 | RGB 565 pixels, row-major, high byte first        |
+    /// | 2     | Checksum, wrapping sum of the pixel payload bytes |
+    /// | 2     | CRC-16/CCITT-FALSE of the payload (`OVFC` only)   |
+    ///
+    /// The CRC is enabled with `set_stream_config`; unlike the checksum it
+    /// catches swapped and doubled bytes, so a host can drop (or, with RX
+    /// wired, ask for) a frame damaged on the line. The frame is captured in
+    /// full before sending, so the capture isn't slowed by the link. At
+    /// 115200 baud a 160x120 frame takes ~3.5s; with
+    /// `UsartDebugger::with_dma` each row goes out in a single transfer.
+    #[cfg(feature = "usart")]
+    pub fn stream_frame(&self, frame: &mut FrameBuffer, out: &mut impl WriteBytes) -> Result<CaptureStats, CaptureError> {
+
+        const MAGIC: &[u8; 4] = b"OVF1";
+        const MAGIC_CRC: &[u8; 4] = b"OVFC";
+
+        let with_crc = self.stream_config.get().crc;
+
+        let stats = self.capture_frame(frame)?;
+        let rows = self.frame_rows();
+
+        out.write_bytes(if with_crc { MAGIC_CRC } else { MAGIC });
+        out.write_bytes(&(FrameBuffer::WIDTH as u16).to_le_bytes());
+        out.write_bytes(&(rows as u16).to_le_bytes());
+
+        let mut checksum: u16 = 0;
+        let mut crc = CRC16_INIT;
+
+        // Sent a row at a time so a DMA-backed sink moves it in one transfer
+        let mut bytes = [0; FrameBuffer::WIDTH * 2];
+
+        for y in 0..rows {
+
+            for (pair, pixel) in bytes.chunks_exact_mut(2).zip(frame.row(y)) {
+                pair.copy_from_slice(&pixel.0.to_be_bytes());
+            }
+
+            checksum = bytes.iter().fold(checksum, |sum, &byte| sum.wrapping_add(byte as u16));
+
+            if with_crc {
+                crc = crc16(crc, &bytes);
+            }
+
+            out.write_bytes(&bytes);
+        }
+
+        out.write_bytes(&checksum.to_le_bytes());
+
+        if with_crc {
+            out.write_bytes(&crc.to_le_bytes());
+        }
+
+        Ok(stats)
+    }
+
+    /// Record every byte the camera clocks out over the next frame
+    ///
+    /// Bytes are stored in the order they're sampled, one per PCLK edge while
+    /// HREF is active, with no pixel assembly: in RGB 565 each pixel is its
+    /// high byte then its low byte, other formats (YUV, raw Bayer) come out
+    /// in the sensor's own order. Lines are concatenated without padding.
+    ///
+    /// Returns the number of bytes the camera sent, which is more than was
+    /// stored if `buf` was too short. The frame ends at the next VSYNC.
+    pub fn capture_raw(&self, buf: &mut [u8]) -> Result<usize, CaptureError> {
+
+        let idle = self.pclk_inverted.get();
+
+        self.wait_frame_start()?;
+
+        let mut count = 0;
+
+        loop {
+
+            // Wait for the next line, or VSYNC marking the end of the frame
+            if !wait_while(OV7670::HREF_TIMEOUT, || !self.read_href() && !self.read_vsync()) {
+                return Err(CaptureError::HrefTimeout);
+            }
+
+            if !self.read_href() {
+                return Ok(count);
+            }
+
+            while self.read_href() {
+
+                self.wait_pclk(idle)?;
+
+                if let Some(byte) = buf.get_mut(count) {
+                    *byte = self.read_data();
+                }
+
+                count += 1;
+
+                self.wait_pclk(!idle)?;
+            }
+        }
+    }
+
+    /// Capture a frame and return the pixel at its center
+    ///
+    /// Only the lines up to the center are read, so no frame buffer is
+    /// needed. Useful for checking color orientation and tuning WB gains.
+    pub fn sample_center(&self) -> Result<Rgb565, CaptureError> {
+
+        self.wait_frame_start()?;
+
+        let mut buf = [Rgb565::BLACK; OV7670::FRAME_WIDTH];
+
+        for _ in 0..=self.frame_height.get() / 2 {
+            self.capture_line(&mut buf)?;
+        }
+
+        Ok(buf[OV7670::FRAME_WIDTH / 2])
+    }
+
+    /// Check that an OV7670 is responding on the SCCB bus
+    ///
+    /// Reads the product ID (PID/VER) registers and compares them against the
+    /// values documented for the OV7670.
+    pub fn probe(&self) -> bool {
+
+        const PID_ADDR: u8 = 0x0A;
+        const PID_OV7670: u8 = 0x76;
+
+        const VER_ADDR: u8 = 0x0B;
+        const VER_OV7670: u8 = 0x73;
+
+        self.sccb_read(PID_ADDR) == Ok(PID_OV7670) && self.sccb_read(VER_ADDR) == Ok(VER_OV7670)
+    }
+
+    /// Read the manufacturer ID (MIDH/MIDL), 0x7FA2 for OmniVision
+    ///
+    /// Clones that answer `probe` with the right PID/VER sometimes report a
+    /// different manufacturer, which is worth knowing before chasing odd
+    /// colors or timings.
+    pub fn read_manufacturer_id(&self) -> Result<u16, I2cError> {
+
+        const MIDH_ADDR: u8 = 0x1C;
+        const MIDL_ADDR: u8 = 0x1D;
+
+        Ok((self.sccb_read(MIDH_ADDR)? as u16) << 8 | self.sccb_read(MIDL_ADDR)? as u16)
+    }
+
+    /// Write registers 0x00-0x6F to `out` as a hex table, 16 to a line
+    ///
+    /// Covers the sensor's configuration (clocks, format, windowing, AEC/AGC,
+    /// AWB, color matrix), so two modules that behave differently can be
+    /// compared line by line. A register that fails to read is shown as
+    /// `--` and the dump carries on.
+    pub fn dump_registers(&self, out: &mut impl fmt::Write) -> fmt::Result {
+
+        const LAST_ADDR: u8 = 0x6F;
+
+        write!(out, "    ")?;
+        for column in 0..16 {
+            write!(out, " {:X} ", column)?;
+        }
+        write!(out, "\r\n")?;
+
+        for row in (0..=LAST_ADDR).step_by(16) {
+
+            write!(out, "{:02X}: ", row)?;
+
+            for addr in row..=row + 15 {
+                match self.sccb_read(addr) {
+                    Ok(value) => write!(out, "{:02X} ", value)?,
+                    Err(_) => write!(out, "-- ")?
+                }
+            }
+
+            write!(out, "\r\n")?;
+        }
+
+        Ok(())
+    }
+
+    /// Check that XCLK is being driven out on PA8
+    ///
+    /// Confirms HSI is running, MCO1 is sourced from it and PA8 is still
+    /// routed to MCO1, catching clock or pin changes made after `new` (which
+    /// already waits for HSI). A sensor that also passes `probe` is receiving
+    /// it, as the OV7670 doesn't answer SCCB without XCLK.
+    pub fn xclk_configured(&self) -> bool {
+
+        let hsi_running = self.rcc.cr.read().hsirdy().is_ready();
+        let mco1_hsi = self.rcc.cfgr.read().mco1().is_hsi();
+        let pa8_mco1 = self.gpioa.moder.read().moder8().is_alternate() && self.gpioa.afrh.read().afrh8().is_af0();
+
+        hsi_running && mco1_hsi && pa8_mco1
+    }
+
+    /// Check that the I2C peripheral has no pending errors and the bus is idle
+    pub fn i2c_status_ok(&self) -> bool {
+
+        let sr1 = self.i2c1.sr1.read();
+
+        let error = sr1.berr().bit_is_set()
+            || sr1.arlo().bit_is_set()
+            || sr1.af().bit_is_set()
+            || sr1.ovr().bit_is_set()
+            || sr1.timeout().bit_is_set();
+
+        !error && self.i2c1.sr2.read().busy().bit_is_clear()
+    }
+
+    // Configure I2C1 timing for the configured SCCB speed and enable it
+    fn init_i2c(i2c1: &stm32f401::I2C1, apb1_hz: u32, config: &Config) -> Result<(), InitError> {
+
+        sccb::configure_i2c_timing(i2c1, apb1_hz, config.sccb_speed, config.scl_hz)?;
+
+        // Enable I2C1
+        i2c1.cr1.modify(|_, w| w.pe().enabled());
+
+        Ok(())
+    }
+
+    /// Recover a wedged SCCB bus
+    ///
+    /// Clocks out any transfer the OV7670 is stuck in, then resets and
+    /// reconfigures the I2C peripheral. SCCB reads and writes call this
+    /// automatically and retry once when the bus times out or stays busy.
+    pub fn recover_bus(&self) {
+
+        self.flush_i2c_bus();
+
+        // Reset I2C1 to clear a BUSY flag latched by the glitch
+        self.i2c1.cr1.modify(|_, w| w.swrst().set_bit());
+        self.i2c1.cr1.modify(|_, w| w.swrst().clear_bit());
+
+        // The timing was already accepted for this clock, so this can't fail
+        let _ = OV7670::init_i2c(&self.i2c1, self.apb1_hz.get(), &self.config);
+    }
+
+    /// Recompute the SCCB timing for a new APB1 clock of `apb1_hz`
+    ///
+    /// Call after changing the system clock (e.g. switching to the PLL) so
+    /// SCL stays at the configured rate. The clock is remembered, so a
+    /// later `recover_bus` reconfigures I2C1 for it rather than for
+    /// `CLK_HZ`. On error the previous timing is left in place.
+    ///
+    /// Other clock-derived settings don't follow: the `hal::Tim2Pwm`
+    /// backlight period and the capture and SCCB timeouts are computed from
+    /// `CLK_HZ` at build time and are off by the same ratio after a switch.
+    pub fn configure_i2c_timing(&self, apb1_hz: u32) -> Result<(), InitError> {
+
+        OV7670::init_i2c(&self.i2c1, apb1_hz, &self.config)?;
+        self.apb1_hz.set(apb1_hz);
+
+        Ok(())
+    }
+
+    // Restore I2C bus to IDLE state
+    fn flush_i2c_bus(&self) {
+
+        // Re-configure SCL and SDA as outputs
+        self.gpiob.moder.modify(|_, w| {
+            w.moder8().output()
+             .moder9().output()
+        });
+
+        // Attempt to put the bus into the IDLE state (SCL & SDA high)
+        self.gpiob.bsrr.write(|w| {
+            w.bs8().set_bit()
+             .bs9().set_bit()
+        });
+
+        // Manually flush the bus if OV7670 is still driving SDA low
+        for _ in 0..9 {
+
+            // If SDA is high, the bus is flushed
+            if self.gpiob.idr.read().idr9().bit_is_set() {
+                break;
+            }
+
+            self.gpiob.bsrr.write(|w| w.br8().set_bit()); // SCL low
+            asm::delay(1000);
+
+            self.gpiob.bsrr.write(|w| w.bs8().set_bit()); // SCL high
+            asm::delay(1000);
+        }
+
+        // Generate a manual stop signal (SDA rises while SCL is high)
+        self.gpiob.bsrr.write(|w| w.br9().set_bit()); // SDA low
+        asm::delay(1000);
+        self.gpiob.bsrr.write(|w| w.bs8().set_bit()); // SCL high
+        asm::delay(1000);
+        self.gpiob.bsrr.write(|w| w.bs9().set_bit()); // SDA high
+        asm::delay(1000);
+
+        // Restore SCL as I2C1_SCL
+        self.gpiob.moder.modify(|_, w| w.moder8().alternate());
+        self.gpiob.afrh.modify(|_, w| w.afrh8().af4());
+
+        // Restore SDA as I2C1_SDA
+        self.gpiob.moder.modify(|_, w| w.moder9().alternate());
+        self.gpiob.afrh.modify(|_, w| w.afrh9().af4());
+    }
+
+    // Capture the lines of the current frame straight to the display, in a
+    // buffer of `WIDTH` pixels
+    #[cfg(feature = "display")]
+    fn draw_lines<const WIDTH: usize>(&self, display: &ST7735) -> Result<(), CaptureError> {
+
+        // As many lines as the sensor outputs at the current scaling
+        let height = self.frame_height.get();
+
+        // RGB 565 buffer
+        let mut buf = [Rgb565::BLACK; WIDTH];
+
+        let mut lines = 0;
+
+        // Each line is sent as soon as it's captured, into one window
+        let result = display.stream_rows(0, height, &mut buf, |buf| {
+            self.capture_line(buf)?;
+            lines += 1;
+            Ok(())
+        });
+
+        if self.debug_overlay.get() {
+            self.draw_overlay(display, lines, height);
+        }
+
+        result
+    }
+
+    // Draw sync pin states and a captured-lines bar along the top of the panel
+    #[cfg(feature = "display")]
+    fn draw_overlay(&self, display: &ST7735, lines: u32, expected: u32) {
+
+        const SIZE: u32 = 6;
+        const PITCH: u32 = SIZE + 2;
+
+        let level = |high: bool| if high { Rgb565::GREEN } else { Rgb565::RED };
+
+        // VSYNC, HREF, PCLK
+        display.fill_rect(0, 0, SIZE, SIZE, level(self.read_vsync()));
+        display.fill_rect(PITCH, 0, SIZE, SIZE, level(self.read_href()));
+        display.fill_rect(2 * PITCH, 0, SIZE, SIZE, level(self.read_pclk()));
+
+        // Fraction of the frame's lines that were captured
+        let bar_x = 3 * PITCH;
+        let bar_w = display.width().saturating_sub(bar_x);
+        let filled = bar_w * lines.min(expected) / expected;
+
+        display.fill_rect(bar_x, 0, filled, SIZE, Rgb565::CYAN);
+        display.fill_rect(bar_x + filled, 0, bar_w - filled, SIZE, Rgb565::BLACK);
+    }
+
+    // Skip the next `settle_frames` frames, after a change that tears them
+    fn begin_settling(&self) {
+        self.settling.set(self.settle_frames.get());
+    }
+
+    // SCCB bus of the sensor, SCL is read back on PB8
+    fn sccb(&self) -> sccb::Sccb1<'_> {
+        sccb::Sccb1::new(&self.i2c1, self.gpiob)
+    }
+
+    // Issue a register read on the OV7670, recovering the bus once on a timeout
+    // or a bus stuck busy. Failures are pushed to `FAULTS`
+    fn sccb_read(&self, addr: u8) -> Result<u8, I2cError> {
+        let result = match sccb::read(&self.sccb(), OV7670::I2C_ADDR, addr) {
+            Err(I2cError::Timeout | I2cError::Busy) => {
+                self.recover_bus();
+                sccb::read(&self.sccb(), OV7670::I2C_ADDR, addr)
+            }
+            result => result
+        };
+        result.inspect_err(|&error| FAULTS.push(Fault::CameraSccb { addr, error }))
+    }
+
+    // Issue a register write on the OV7670, recovering the bus once on a timeout
+    // or a bus stuck busy. Failures are pushed to `FAULTS`
+    fn sccb_write(&self, addr: u8, data: u8) -> Result<(), I2cError> {
+        let result = match sccb::write(&self.sccb(), OV7670::I2C_ADDR, addr, data) {
+            Err(I2cError::Timeout | I2cError::Busy) => {
+                self.recover_bus();
+                sccb::write(&self.sccb(), OV7670::I2C_ADDR, addr, data)
+            }
+            result => result
+        };
+        result.inspect_err(|&error| FAULTS.push(Fault::CameraSccb { addr, error }))
+    }
+
+    // Read-modify-write the bits selected by `mask`, preserving the rest
+    fn sccb_modify(&self, addr: u8, mask: u8, value: u8) -> Result<(), I2cError> {
+        let current = self.sccb_read(addr)?;
+        self.sccb_write(addr, (current & !mask) | (value & mask))
+    }
+
+    // Sample pixels while HREF is active, returning how many were clocked out
+    // Works with any data bus mapping
+    fn sample_line(&self, buf: &mut [Rgb565]) -> Result<usize, CaptureError> {
+
+        // Data is valid on the pclk edge leaving `idle`
+        // Rising edge normally, falling edge when inverted
+        let idle = self.pclk_inverted.get();
+
+        let mut x = 0;
+
+        // only sample while href marks the data as valid
+        while self.read_href() {
+
+            // wait for pclk sampling edge
+            self.wait_pclk(idle)?;
+
+            let data_msb: u8 = self.read_data();
+
+            // wait for pclk to return to idle
+            self.wait_pclk(!idle)?;
+
+            // wait for pclk sampling edge
+            self.wait_pclk(idle)?;
+
+            let data_lsb: u8 = self.read_data();
+
+            // Concat data MSB and LSB
+            let data: u16 = ((data_msb as u16) << 8) | (data_lsb as u16);
+
+            if x < buf.len() {
+                buf[x] = Rgb565(data);
+            }
+
+            x += 1;
+
+            self.wait_pclk(!idle)?; // wait for pclk to return to idle
+        }
+
+        Ok(x)
+    }
+
+    // `sample_line` for a contiguous data bus
+    //
+    // Between a PCLK edge and the data read there's only a masked compare
+    // and a branch: the IDR addresses, PCLK mask and idle level are resolved
+    // up front, both raw IDR words are kept until the pixel is complete, and
+    // the timeout is a plain countdown instead of a `wait_while` closure.
+    // See `selftest::benchmark_capture` for the cycle counts.
+    fn sample_line_fast(&self, buf: &mut [Rgb565], port: Port, offset: u8) -> Result<usize, CaptureError> {
+
+        const PCLK: u32 = 1 << 9;
+
+        let data_idr = self.idr_ptr(port);
+        let sync_idr = self.idr_ptr(Port::A);
+        let idle = if self.pclk_inverted.get() { PCLK } else { 0 };
+
+        // Wait while PCLK is at `level`
+        let wait = |level: u32| {
+            let mut budget = OV7670::PCLK_TIMEOUT;
+            // SAFETY: GPIO IDR registers are always readable
+            while unsafe { sync_idr.read_volatile() } & PCLK == level {
+                budget -= 1;
+                if budget == 0 {
+                    return Err(CaptureError::PclkTimeout);
+                }
+            }
+            Ok(())
+        };
+
+        let mut x = 0;
+
+        while self.read_href() {
+
+            wait(idle)?;
+            let msb = unsafe { data_idr.read_volatile() };
+            wait(idle ^ PCLK)?;
+
+            wait(idle)?;
+            let lsb = unsafe { data_idr.read_volatile() };
+
+            if let Some(pixel) = buf.get_mut(x) {
+                *pixel = Rgb565((((msb >> offset) & 0xFF) << 8 | ((lsb >> offset) & 0xFF)) as u16);
+            }
+
+            x += 1;
+
+            wait(idle ^ PCLK)?;
+        }
+
+        Ok(x)
+    }
+
+    // Core cycles for `iterations` PCLK checks and data reads through the
+    // generic and fast sampling paths, `None` without the DWT cycle counter
+    // Only used by `selftest`, which also needs the display
+    #[cfg(feature = "display")]
+    pub(crate) fn sample_cycles(&self, iterations: u32) -> Option<(u32, u32)> {
+
+        if !DWT::cycle_counter_enabled() {
+            return None;
+        }
+
+        let start = DWT::cycle_count();
+        for _ in 0..iterations {
+            core::hint::black_box((self.read_pclk(), self.read_data()));
+        }
+        let generic = DWT::cycle_count().wrapping_sub(start);
+
+        let (port, offset) = match self.data_bus {
+            DataBus::Contiguous { port, offset } => (port, offset),
+            DataBus::Pins(_) => return Some((generic, generic))
+        };
+
+        let data_idr = self.idr_ptr(port);
+        let sync_idr = self.idr_ptr(Port::A);
+
+        let start = DWT::cycle_count();
+        for _ in 0..iterations {
+            let pclk = unsafe { sync_idr.read_volatile() } & (1 << 9);
+            let data = unsafe { data_idr.read_volatile() } >> offset;
+            core::hint::black_box((pclk, data as u8));
+        }
+        let fast = DWT::cycle_count().wrapping_sub(start);
+
+        Some((generic, fast))
+    }
+
+    // Wait while VSYNC is at `level`
+    fn wait_vsync(&self, level: bool) -> Result<(), CaptureError> {
+        match wait_while(OV7670::VSYNC_TIMEOUT, || self.read_vsync() == level) {
+            true => Ok(()),
+            false => Err(CaptureError::VsyncTimeout)
+        }
+    }
+
+    // Rows of a `FrameBuffer` the sensor fills at the current scaling
+    pub(crate) fn frame_rows(&self) -> usize {
+        (self.frame_height.get() as usize).min(FrameBuffer::HEIGHT)
+    }
+
+    // Wait for the next line's HREF, returning true if VSYNC ends the frame
+    // first because lines went missing
+    pub(crate) fn frame_ended_before_line(&self) -> Result<bool, CaptureError> {
+        match wait_while(OV7670::HREF_TIMEOUT, || !self.read_href() && !self.read_vsync()) {
+            true => Ok(!self.read_href()),
+            false => Err(CaptureError::HrefTimeout)
+        }
+    }
+
+    // Wait while HREF activity is `active`
+    pub(crate) fn wait_href(&self, active: bool) -> Result<(), CaptureError> {
+        match wait_while(OV7670::HREF_TIMEOUT, || self.read_href() == active) {
+            true => Ok(()),
+            false => Err(CaptureError::HrefTimeout)
+        }
+    }
+
+    // Wait while PCLK is at `level`
+    fn wait_pclk(&self, level: bool) -> Result<(), CaptureError> {
+        match wait_while(OV7670::PCLK_TIMEOUT, || self.read_pclk() == level) {
+            true => Ok(()),
+            false => Err(CaptureError::PclkTimeout)
+        }
+    }
+
+    // Whether VSYNC is at its active level, accounting for polarity
+    fn read_vsync(&self) -> bool {
+        self.gpioa.idr.read().idr6().bit() != self.vsync_inverted.get()
+    }
+
+    // Whether HREF marks the data bus as valid, accounting for polarity
+    pub(crate) fn read_href(&self) -> bool {
+        self.gpiob.idr.read().idr3().bit() != self.href_inverted.get()
+    }
+
+    fn read_pclk(&self) -> bool {
+        self.gpioa.idr.read().idr9().bit()
+    }
+
+    fn read_data(&self) -> u8 {
+        match self.data_bus {
+            DataBus::Contiguous { port, offset } => (self.read_port(port) >> offset) as u8,
+            DataBus::Pins(pins) => {
+                // One read per port, so every bit comes from the same moment
+                let idr = [Port::A, Port::B, Port::C].map(|port| self.read_port(port));
+                let mut data = 0;
+                for (bit, (port, pin)) in pins.into_iter().enumerate() {
+                    data |= (((idr[port as usize] >> pin) & 1) as u8) << bit;
+                }
+                data
+            }
+        }
+    }
+
+    // Address of the IDR byte holding D0-D7, if the bus is byte aligned
+    pub(crate) fn data_byte_address(&self) -> Option<u32> {
+
+        let (port, offset) = match self.data_bus {
+            DataBus::Contiguous { port, offset: offset @ (0 | 8) } => (port, offset),
+            _ => return None
+        };
+
+        Some(self.idr_ptr(port) as u32 + offset as u32 / 8)
+    }
+
+    // Input data register of a port
+    fn idr_ptr(&self, port: Port) -> *const u32 {
+        match port {
+            Port::A => self.gpioa.idr.as_ptr(),
+            Port::B => self.gpiob.idr.as_ptr(),
+            Port::C => self.gpioc.idr.as_ptr()
+        }
+    }
+
+    fn read_port(&self, port: Port) -> u32 {
+        match port {
+            Port::A => self.gpioa.idr.read().bits(),
+            Port::B => self.gpiob.idr.read().bits(),
+            Port::C => self.gpioc.idr.read().bits()
+        }
+    }
+
+    // Configure a single pin as a floating input
+    fn configure_input(
+        gpioa: &stm32f401::GPIOA,
+        gpiob: &stm32f401::GPIOB,
+        gpioc: &stm32f401::GPIOC,
+        port: Port,
+        pin: u8
+    ) {
+        let mask = !(0b11 << (2 * pin as u32));
+
+        match port {
+            Port::A => gpioa.moder.modify(|r, w| unsafe { w.bits(r.bits() & mask) }),
+            Port::B => gpiob.moder.modify(|r, w| unsafe { w.bits(r.bits() & mask) }),
+            Port::C => gpioc.moder.modify(|r, w| unsafe { w.bits(r.bits() & mask) })
+        }
+    }
+
+    // Configure a single pin as a push-pull output
+    fn configure_output(
+        gpioa: &stm32f401::GPIOA,
+        gpiob: &stm32f401::GPIOB,
+        gpioc: &stm32f401::GPIOC,
+        port: Port,
+        pin: u8
+    ) {
+        let shift = 2 * pin as u32;
+        let set = |bits: u32| (bits & !(0b11 << shift)) | (0b01 << shift);
+
+        match port {
+            Port::A => gpioa.moder.modify(|r, w| unsafe { w.bits(set(r.bits())) }),
+            Port::B => gpiob.moder.modify(|r, w| unsafe { w.bits(set(r.bits())) }),
+            Port::C => gpioc.moder.modify(|r, w| unsafe { w.bits(set(r.bits())) })
+        }
+    }
+
+    // Drive an output pin high or low
+    fn write_output(
+        gpioa: &stm32f401::GPIOA,
+        gpiob: &stm32f401::GPIOB,
+        gpioc: &stm32f401::GPIOC,
+        port: Port,
+        pin: u8,
+        high: bool
+    ) {
+        let bit = 1 << if high { pin } else { pin + 16 };
+
+        match port {
+            Port::A => gpioa.bsrr.write(|w| unsafe { w.bits(bit) }),
+            Port::B => gpiob.bsrr.write(|w| unsafe { w.bits(bit) }),
+            Port::C => gpioc.bsrr.write(|w| unsafe { w.bits(bit) })
+        }
+    }
+}
+
+/// Iterator over the lines of a single frame, see `OV7670::lines`
+pub struct Lines<'c, 'a> {
+    camera: &'c OV7670<'a>,
+    remaining: usize
+}
+
+impl Iterator for Lines<'_, '_> {
+
+    type Item = Result<[Rgb565; OV7670::FRAME_WIDTH], CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+
+        if self.remaining == 0 {
+            return None;
+        }
+
+        self.remaining -= 1;
+
+        let mut line = [Rgb565::BLACK; OV7670::FRAME_WIDTH];
+
+        match self.camera.capture_line(&mut line) {
+            Ok(()) => Some(Ok(line)),
+            Err(error) => {
+                // The frame is lost, stop iterating
+                self.remaining = 0;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn complete_scaled_frame_drops_nothing() {
+
+        // 60 rows at /4, then VSYNC
+        let mut stats = CaptureStats::default();
+        let result = stats.capture_rows(60, |y| Ok(if y < 60 { Some(false) } else { None }));
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(stats.rows, 60);
+        assert_eq!(stats.dropped_hsync, 0);
+    }
+
+    #[test]
+    fn early_vsync_drops_the_rows_still_expected() {
+
+        let mut stats = CaptureStats::default();
+        let result = stats.capture_rows(120, |y| Ok(if y < 100 { Some(y == 0) } else { None }));
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(stats.rows, 100);
+        assert_eq!(stats.dropped_hsync, 1 + 20);
     }
 }
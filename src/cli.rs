@@ -0,0 +1,186 @@
+use core::{fmt::{self, Write}, num::NonZeroU8};
+
+use crate::{camera::{ColorEffect, OV7670}, display::{Display, ST7735}};
+
+/*
+    Serial command line for live tuning
+
+    Feed received bytes into a `LineBuffer` and pass each completed line to
+    `handle`, e.g. from the `Camera::run` per-frame callback:
+
+    exposure 120      manual exposure (or `exposure auto`)
+    gain 40           manual analog gain
+    fps 15            closest achievable frame rate (or `fps` for the current)
+    mirror on         horizontal mirror
+    effect sepia      none, negative, sepia or bw
+    brightness 128    software display dimming
+    overlay on        capture debug overlay
+    pattern bars      display test pattern (or `pattern grid`)
+    id                camera manufacturer ID
+    regs              camera register dump, for bug reports
+    help
+
+    Replies are echoed to `out`. Nothing allocates, lines longer than the
+    buffer are truncated.
+*/
+
+/// Accumulates received bytes into lines for `handle`
+pub struct LineBuffer {
+    buf: [u8; LineBuffer::CAPACITY],
+    len: usize
+}
+
+impl LineBuffer {
+
+    const CAPACITY: usize = 32;
+
+    pub const fn new() -> Self {
+        LineBuffer { buf: [0; LineBuffer::CAPACITY], len: 0 }
+    }
+
+    /// Add a received byte, returning the line once CR or LF completes it
+    ///
+    /// Empty lines and lines that aren't valid UTF-8 are dropped.
+    pub fn push(&mut self, byte: u8) -> Option<&str> {
+        match byte {
+            b'\r' | b'\n' => {
+                let len = core::mem::take(&mut self.len);
+                core::str::from_utf8(&self.buf[..len]).ok().filter(|line| !line.is_empty())
+            }
+            _ => {
+                if self.len < self.buf.len() {
+                    self.buf[self.len] = byte;
+                    self.len += 1;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        LineBuffer::new()
+    }
+}
+
+/// Run one command line against the camera and display, echoing the result
+///
+/// Each command takes at most one argument. Unknown commands and bad
+/// arguments are reported to `out` rather than returned as errors.
+pub fn handle(line: &str, out: &mut impl Write, camera: &OV7670, display: &ST7735) -> fmt::Result {
+
+    let mut words = line.split_whitespace();
+
+    let (Some(command), argument, None) = (words.next(), words.next(), words.next()) else {
+        return write!(out, "? expected a command and at most one argument\r\n");
+    };
+
+    match (command, argument) {
+
+        ("exposure", Some("auto")) => reply(out, camera.set_auto_exposure(true).is_ok(), "auto exposure"),
+
+        ("exposure", Some(value)) => match value.parse() {
+            Ok(value) => reply(out, camera.set_exposure(value).is_ok(), "exposure set"),
+            Err(_) => write!(out, "? exposure must be 0-65535 or auto\r\n")
+        },
+
+        ("gain", Some(value)) => match value.parse() {
+            Ok(value) => reply(out, camera.set_gain(value).is_ok(), "gain set"),
+            Err(_) => write!(out, "? gain must be 0-255\r\n")
+        },
+
+        ("fps", Some(value)) => match parse_fps(value) {
+            Some(value) => match camera.set_framerate(value) {
+                Ok(achieved) => write!(out, "ok {} fps\r\n", achieved),
+                Err(error) => write!(out, "! {:?}\r\n", error)
+            },
+            None => write!(out, "? fps must be 1-255\r\n")
+        },
+
+        ("fps", None) => match camera.framerate() {
+            Ok(fps) => write!(out, "ok {} fps\r\n", fps),
+            Err(error) => write!(out, "! {:?}\r\n", error)
+        },
+
+        ("mirror", Some(value @ ("on" | "off"))) => reply(out, camera.set_mirror(value == "on").is_ok(), "mirror set"),
+
+        ("effect", Some(value)) => {
+            let effect = match value {
+                "none" => ColorEffect::None,
+                "negative" => ColorEffect::Negative,
+                "sepia" => ColorEffect::Sepia,
+                "bw" => ColorEffect::BlackWhite,
+                _ => return write!(out, "? effect must be none, negative, sepia or bw\r\n")
+            };
+            reply(out, camera.set_effect(effect).is_ok(), "effect set")
+        }
+
+        ("brightness", Some(value)) => match value.parse() {
+            Ok(value) => {
+                display.set_software_brightness(value);
+                write!(out, "ok brightness set\r\n")
+            }
+            Err(_) => write!(out, "? brightness must be 0-255\r\n")
+        },
+
+        ("overlay", Some(value @ ("on" | "off"))) => {
+            camera.set_debug_overlay(value == "on");
+            write!(out, "ok overlay {}\r\n", value)
+        }
+
+        ("pattern", Some("bars")) => {
+            display.draw_test_pattern();
+            write!(out, "ok pattern drawn\r\n")
+        }
+
+        ("pattern", Some("grid")) => {
+            display.draw_calibration_grid(16);
+            write!(out, "ok grid drawn\r\n")
+        }
+
+        ("pattern", Some("off")) => {
+            display.clear();
+            write!(out, "ok pattern cleared\r\n")
+        }
+
+        ("id", None) => match camera.read_manufacturer_id() {
+            Ok(id) => write!(out, "ok manufacturer {:04X}\r\n", id),
+            Err(error) => write!(out, "! {:?}\r\n", error)
+        },
+
+        ("regs", None) => camera.dump_registers(out),
+
+        ("help", None) => write!(out, "exposure gain fps mirror effect brightness overlay pattern id regs\r\n"),
+
+        _ => write!(out, "? unknown command '{}', try help\r\n", line)
+    }
+}
+
+// Frame rate argument, 0 has no achievable rate
+fn parse_fps(value: &str) -> Option<u8> {
+    value.parse::<NonZeroU8>().ok().map(NonZeroU8::get)
+}
+
+// Echo whether a setter reached the camera
+fn reply(out: &mut impl Write, ok: bool, message: &str) -> fmt::Result {
+    match ok {
+        true => write!(out, "ok {}\r\n", message),
+        false => write!(out, "! SCCB write failed\r\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn fps_rejects_zero_and_out_of_range() {
+        assert_eq!(parse_fps("15"), Some(15));
+        assert_eq!(parse_fps("255"), Some(255));
+        assert_eq!(parse_fps("0"), None);
+        assert_eq!(parse_fps("256"), None);
+        assert_eq!(parse_fps("fast"), None);
+    }
+}
@@ -0,0 +1,242 @@
+/// 16-bit RGB 565 color, the native output format of the OV7670
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Rgb565(pub u16);
+
+impl Rgb565 {
+
+    pub const BLACK: Rgb565 = Rgb565(0x0000);
+    pub const WHITE: Rgb565 = Rgb565(0xFFFF);
+    pub const RED: Rgb565 = Rgb565(0xF800);
+    pub const GREEN: Rgb565 = Rgb565(0x07E0);
+    pub const BLUE: Rgb565 = Rgb565(0x001F);
+    pub const YELLOW: Rgb565 = Rgb565(0xFFE0);
+    pub const CYAN: Rgb565 = Rgb565(0x07FF);
+    pub const MAGENTA: Rgb565 = Rgb565(0xF81F);
+
+    /// Pack an RGB 888 color, truncating each channel to its high bits
+    pub const fn from_rgb888(red: u8, green: u8, blue: u8) -> Self {
+        Rgb565(((red as u16 >> 3) << 11) | ((green as u16 >> 2) << 5) | (blue as u16 >> 3))
+    }
+
+    /// Expand to RGB 888
+    pub const fn to_rgb888(self) -> (u8, u8, u8) {
+        rgb565_to_rgb888(self.0)
+    }
+
+    /// Integer BT.601 luminance (0-255)
+    pub const fn luma(self) -> u8 {
+        let (r, g, b) = self.to_rgb888();
+        ((77 * r as u32 + 150 * g as u32 + 29 * b as u32) >> 8) as u8
+    }
+
+    /// Exchange the red and blue channels
+    pub const fn swap_rb(self) -> Self {
+        let red = (self.0 >> 11) & 0x1F;
+        let blue = self.0 & 0x1F;
+        Rgb565((blue << 11) | (self.0 & 0x07E0) | red)
+    }
+
+    /// Mix with `other`, taking `weight / 256` of it and the rest of `self`
+    ///
+    /// Channels are mixed in RGB 565 space, so 128 is an even 50/50 blend.
+    pub fn blend(self, other: Rgb565, weight: u8) -> Self {
+
+        let mix = |shift: u16, mask: u16| {
+            let a = ((self.0 >> shift) & mask) as u32;
+            let b = ((other.0 >> shift) & mask) as u32;
+            (((a * (256 - weight as u32) + b * weight as u32) >> 8) as u16 & mask) << shift
+        };
+
+        Rgb565(mix(11, 0x1F) | mix(5, 0x3F) | mix(0, 0x1F))
+    }
+}
+
+/// Expand an RGB 565 value to RGB 888
+///
+/// The high bits of each channel are replicated into the low bits so
+/// full-scale inputs map to 0xFF.
+pub const fn rgb565_to_rgb888(color: u16) -> (u8, u8, u8) {
+
+    let red5 = (color >> 11) & 0x1F;
+    let green6 = (color >> 5) & 0x3F;
+    let blue5 = color & 0x1F;
+
+    let red = (red5 << 3) | (red5 >> 2);
+    let green = (green6 << 2) | (green6 >> 4);
+    let blue = (blue5 << 3) | (blue5 >> 2);
+
+    (red as u8, green as u8, blue as u8)
+}
+
+/// Lookup table expanding RGB 565 channels to RGB 888 with a brightness scale
+/// and per-channel gains
+///
+/// Folding the scale into the channel expansion keeps the per-pixel cost at
+/// one lookup per channel. At full brightness and unity gains it matches
+/// `rgb565_to_rgb888`.
+#[derive(Copy, Clone)]
+pub struct ChannelLut {
+    red: [u8; 32],
+    green: [u8; 64],
+    blue: [u8; 32],
+    level: u8,
+    gains: (u8, u8, u8)
+}
+
+impl ChannelLut {
+
+    /// Gain that leaves a channel unchanged
+    pub const UNITY_GAIN: u8 = 128;
+
+    /// Build a table scaling every channel by `level / 255`
+    pub fn new(level: u8) -> Self {
+        ChannelLut::with_gains(level, (ChannelLut::UNITY_GAIN, ChannelLut::UNITY_GAIN, ChannelLut::UNITY_GAIN))
+    }
+
+    /// Build a table scaling every channel by `level / 255` and each of red,
+    /// green and blue by its gain / 128, saturating at full scale
+    pub fn with_gains(level: u8, gains: (u8, u8, u8)) -> Self {
+
+        let (red_gain, green_gain, blue_gain) = gains;
+
+        let scale = |value: u8, gain: u8| ChannelLut::scale(value, level, gain);
+
+        let mut lut = ChannelLut { red: [0; 32], green: [0; 64], blue: [0; 32], level, gains };
+
+        for i in 0..32 {
+            let (red, _, blue) = rgb565_to_rgb888((i << 11) | i);
+            lut.red[i as usize] = scale(red, red_gain);
+            lut.blue[i as usize] = scale(blue, blue_gain);
+        }
+
+        for i in 0..64 {
+            let (_, green, _) = rgb565_to_rgb888(i << 5);
+            lut.green[i as usize] = scale(green, green_gain);
+        }
+
+        lut
+    }
+
+    /// Expand a color to RGB 888 through the table
+    pub fn apply(&self, color: Rgb565) -> (u8, u8, u8) {
+        (
+            self.red[(color.0 >> 11) as usize & 0x1F],
+            self.green[(color.0 >> 5) as usize & 0x3F],
+            self.blue[color.0 as usize & 0x1F]
+        )
+    }
+
+    /// Scale an RGB 888 color by the same brightness and gains
+    ///
+    /// Computed per channel rather than looked up, so full 8-bit inputs
+    /// keep their low bits.
+    pub fn apply_rgb888(&self, color: (u8, u8, u8)) -> (u8, u8, u8) {
+        let (red_gain, green_gain, blue_gain) = self.gains;
+        (
+            ChannelLut::scale(color.0, self.level, red_gain),
+            ChannelLut::scale(color.1, self.level, green_gain),
+            ChannelLut::scale(color.2, self.level, blue_gain)
+        )
+    }
+
+    // Scale a channel by `level / 255` and `gain / 128`, saturating
+    fn scale(value: u8, level: u8, gain: u8) -> u8 {
+        let scaled = value as u32 * level as u32 * gain as u32 / (255 * ChannelLut::UNITY_GAIN as u32);
+        scaled.min(u8::MAX as u32) as u8
+    }
+}
+
+/// Pixel formats the display draw paths accept
+///
+/// Camera lines arrive as RGB 565 and go through the `ChannelLut` lookup,
+/// RGB 888 sources (e.g. pre-rendered overlays) are scaled directly so they
+/// reach the panel without a lossy round trip through RGB 565. Buffered
+/// mode is the exception: the working buffer holds RGB 565, so RGB 888
+/// pixels drawn into it lose their low bits.
+pub trait IntoPanelColor: Copy {
+
+    /// Black, for padding lines narrower than the panel
+    const BLACK: Self;
+
+    /// RGB 888 for the panel, after brightness and gains
+    fn into_panel(self, lut: &ChannelLut) -> (u8, u8, u8);
+
+    /// RGB 565 for the display buffer
+    fn into_rgb565(self) -> Rgb565;
+}
+
+impl IntoPanelColor for Rgb565 {
+
+    const BLACK: Self = Rgb565::BLACK;
+
+    fn into_panel(self, lut: &ChannelLut) -> (u8, u8, u8) {
+        lut.apply(self)
+    }
+
+    fn into_rgb565(self) -> Rgb565 {
+        self
+    }
+}
+
+impl IntoPanelColor for u16 {
+
+    const BLACK: Self = 0;
+
+    fn into_panel(self, lut: &ChannelLut) -> (u8, u8, u8) {
+        lut.apply(Rgb565(self))
+    }
+
+    fn into_rgb565(self) -> Rgb565 {
+        Rgb565(self)
+    }
+}
+
+impl IntoPanelColor for (u8, u8, u8) {
+
+    const BLACK: Self = (0, 0, 0);
+
+    fn into_panel(self, lut: &ChannelLut) -> (u8, u8, u8) {
+        lut.apply_rgb888(self)
+    }
+
+    fn into_rgb565(self) -> Rgb565 {
+        Rgb565::from_rgb888(self.0, self.1, self.2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const UNITY: (u8, u8, u8) = (ChannelLut::UNITY_GAIN, ChannelLut::UNITY_GAIN, ChannelLut::UNITY_GAIN);
+
+    #[test]
+    fn unity_gain_at_full_level_is_the_identity() {
+
+        let lut = ChannelLut::with_gains(255, UNITY);
+
+        for color in [Rgb565::BLACK, Rgb565::WHITE, Rgb565::RED, Rgb565(0x8410), Rgb565(0x1234)] {
+            assert_eq!(lut.apply(color), color.to_rgb888());
+        }
+    }
+
+    #[test]
+    fn gain_above_unity_saturates_at_full_scale() {
+
+        // Red doubled, the others untouched
+        let lut = ChannelLut::with_gains(255, (255, ChannelLut::UNITY_GAIN, ChannelLut::UNITY_GAIN));
+
+        assert_eq!(lut.apply(Rgb565::WHITE), (255, 255, 255));
+
+        let (red, _, _) = Rgb565(0x4000).to_rgb888();
+        assert_eq!(lut.apply(Rgb565(0x4000)).0, (red as u32 * 255 / 128) as u8);
+    }
+
+    #[test]
+    fn level_scales_every_channel() {
+        assert_eq!(ChannelLut::with_gains(128, UNITY).apply(Rgb565::WHITE), (128, 128, 128));
+        assert_eq!(ChannelLut::with_gains(0, UNITY).apply(Rgb565::WHITE), (0, 0, 0));
+    }
+}
@@ -1,2 +1,89 @@
+#[cfg(feature = "camera")]
+use crate::sccb::I2cTiming;
+
 pub const BAUD_RATE: u32 = 115_200;
 pub const CLK_HZ: u32 = 16_000_000;
+
+// Check the default configuration even in builds that never use it, so a
+// `CLK_HZ` or `BAUD_RATE` that overflows BRR, CCR, TRISE or FREQ fails the
+// build instead of wrapping in a constructor
+const _: Config = Config::DEFAULT;
+
+/// SCCB (I2C1) bus mode
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Speed {
+    /// Up to 100KHz
+    Standard,
+    /// Up to 400KHz
+    Fast
+}
+
+/// Peripheral tunables shared by the driver constructors
+///
+/// Build it in a `const` (like `Config::DEFAULT`) so `validate` rejects
+/// invalid clock combinations at compile time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// USART debugger baud rate
+    pub baud_rate: u32,
+    /// SPI1 clock = CLK_HZ / spi_divider, a power of two in [2, 256]
+    pub spi_divider: u32,
+    /// SCCB (I2C1) bus mode, bounds `scl_hz`
+    pub sccb_speed: Speed,
+    /// SCCB (I2C1) clock, up to 100KHz in standard mode or 400KHz in fast mode
+    pub scl_hz: u32,
+    /// Camera XCLK = CLK_HZ / xclk_divider, in [1, 5]
+    pub xclk_divider: u32
+}
+
+impl Config {
+
+    pub const DEFAULT: Config = Config {
+        baud_rate: BAUD_RATE,
+        spi_divider: 8,
+        sccb_speed: Speed::Standard,
+        scl_hz: 100_000,
+        xclk_divider: 1
+    }.validate();
+
+    /// Check the configuration fits the hardware, panicking otherwise
+    ///
+    /// Evaluated in a `const` this turns into a build error.
+    pub const fn validate(self) -> Self {
+
+        assert!(self.baud_rate > 0, "baud rate must be non-zero");
+
+        // BRR = CLK / baud (16x oversampling), mantissa must be non-zero
+        let brr = CLK_HZ / self.baud_rate;
+        assert!(brr >= 16 && brr <= 0xFFFF, "baud rate out of range for USART BRR");
+
+        assert!(
+            self.spi_divider.is_power_of_two() && self.spi_divider >= 2 && self.spi_divider <= 256,
+            "SPI divider must be a power of two in [2, 256]"
+        );
+
+        #[cfg(feature = "camera")]
+        assert!(
+            I2cTiming::new(CLK_HZ, self.sccb_speed, self.scl_hz).is_some(),
+            "SCL out of range for the SCCB speed and I2C input clock (FREQ, CCR or TRISE)"
+        );
+
+        assert!(self.xclk_divider >= 1 && self.xclk_divider <= 5, "XCLK divider must be in [1, 5]");
+
+        self
+    }
+
+    /// USART BRR register value
+    ///
+    /// Saturates rather than wraps for a baud rate `validate` rejects.
+    pub const fn brr(&self) -> u16 {
+        let brr = CLK_HZ / self.baud_rate;
+        if brr > u16::MAX as u32 { u16::MAX } else { brr as u16 }
+    }
+
+    /// SPI CR1.BR field value
+    pub const fn spi_br(&self) -> u8 {
+        // div2 = 0b000 ... div256 = 0b111
+        (self.spi_divider.trailing_zeros() - 1) as u8
+    }
+}
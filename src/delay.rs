@@ -0,0 +1,18 @@
+use cortex_m::asm;
+
+use crate::constants::CLK_HZ;
+
+/// Millisecond delay source used by driver bring-up (`calibrate`)
+pub trait DelayMs {
+    fn delay_ms(&mut self, ms: u32);
+}
+
+/// Busy-wait delay counted in core cycles at `CLK_HZ`
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CycleDelay;
+
+impl DelayMs for CycleDelay {
+    fn delay_ms(&mut self, ms: u32) {
+        asm::delay(CLK_HZ / 1000 * ms);
+    }
+}
@@ -1,7 +1,9 @@
-use cortex_m::asm;
+use core::{cell::{Cell, RefCell}, convert::Infallible};
+
+use embedded_hal::{digital::OutputPin, pwm::SetDutyCycle, spi::SpiBus};
 use stm32f4::stm32f401;
 
-use super::constants::CLK_HZ;
+use super::{color::{ChannelLut, IntoPanelColor, Rgb565}, constants::Config, delay::DelayMs, fault::{Fault, FAULTS}, hal::{GpioaPin, Spi1}, init::InitError};
 
 #[derive(Copy, Clone)]
 pub enum PinState {
@@ -15,6 +17,214 @@ pub enum ControlMode {
     Command
 }
 
+/// Pixel format sent to the panel (COLMOD)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorDepth {
+    /// RGB 444, two pixels packed into 3 bytes
+    Bits12,
+    /// RGB 565, 2 bytes per pixel
+    Bits16,
+    /// RGB 666, 3 bytes per pixel (panel default)
+    Bits18
+}
+
+/// Panel axis a `draw_row` line is drawn along
+///
+/// The panel is taller than it is wide, so by default each camera row is
+/// drawn down a panel column (`ColumnMajor`), which fits a 160 pixel camera
+/// row on a portrait 128x160 panel. Use `RowMajor` for a panel mounted in
+/// landscape or when lines should run along panel rows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AddressMode {
+    /// Line `n` fills panel row `n`, clipped to the panel width
+    RowMajor,
+    /// Line `n` fills panel column `n`, clipped to the panel height (default)
+    ColumnMajor
+}
+
+/// Placement of a `draw_row` line that doesn't match the panel width
+///
+/// Longer lines are clipped and shorter lines padded with black on the
+/// side(s) opposite the alignment, e.g. `Center` drops equally from both ends of a 160 pixel
+/// camera line drawn `RowMajor` on a 128 pixel wide panel. Only used with
+/// `FitMode::Crop`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RowAlign {
+    /// Keep the start of the line, clipping the end (default)
+    Start,
+    Center,
+    /// Keep the end of the line, clipping the start
+    End
+}
+
+/// How `draw_row` fits a line that doesn't match the panel
+///
+/// A 160 pixel camera line on a 128 pixel panel axis loses 32 pixels under
+/// `Crop` and `Center`, while `Scale` keeps the whole field of view.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FitMode {
+    /// Clip or pad according to `RowAlign` (default)
+    Crop,
+    /// Clip or pad equally on both ends, regardless of `RowAlign`
+    Center,
+    /// Nearest-neighbor resample the line to the panel
+    Scale
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DisplayError {
+    /// The SPI bus reported an error, see `ST7735::check_bus`
+    Bus
+}
+
+/// Panel state reported by RDDST, see `ST7735::read_status`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DisplayStatus {
+    /// Booster voltage running
+    pub booster_on: bool,
+    /// In sleep mode, i.e. SLPOUT hasn't been accepted
+    pub sleeping: bool,
+    /// Idle (8-color) mode
+    pub idle: bool,
+    /// Color inversion (INVON)
+    pub inverted: bool,
+    /// Output enabled (DISPON)
+    pub display_on: bool,
+    /// Interface pixel format (COLMOD), `None` for a reserved value
+    pub color_depth: Option<ColorDepth>
+}
+
+/// Delays `calibrate` waits out during bring-up, in milliseconds
+///
+/// `DEFAULT` follows the ST7735 datasheet: 120ms after a reset (hardware or
+/// SWRESET) before the next command, 120ms after SLPOUT for the supply
+/// circuits to settle and a short wait for DISPON to take effect. Panels
+/// that come up faster can use shorter delays with `ST7735::set_timings`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Timings {
+    /// RST held low, the datasheet minimum is 10us
+    pub reset_pulse_ms: u32,
+    /// After RST is released
+    pub reset_ms: u32,
+    /// After SWRESET
+    pub swreset_ms: u32,
+    /// After SLPOUT
+    pub slpout_ms: u32,
+    /// After DISPON
+    pub dispon_ms: u32
+}
+
+impl Timings {
+    pub const DEFAULT: Timings = Timings {
+        reset_pulse_ms: 1,
+        reset_ms: 120,
+        swreset_ms: 120,
+        slpout_ms: 120,
+        dispon_ms: 10
+    };
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Timings::DEFAULT
+    }
+}
+
+/// GPIOA pins driving the display control lines
+///
+/// Panels sharing SPI1 need their own CS, RS and RST can be shared.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ControlPins {
+    pub cs: u8,
+    pub rs: u8,
+    pub rst: u8
+}
+
+impl Default for ControlPins {
+    fn default() -> Self {
+        ControlPins { cs: 0, rs: 4, rst: 1 }
+    }
+}
+
+/// Visible area of a panel and where it sits in the controller RAM
+///
+/// The ST7735 drives up to 132x162 pixels of RAM, but the glass on most
+/// modules is smaller and only covers part of it. Drawing at (0, 0) lands on
+/// the first RAM pixel, so on e.g. a green-tab 1.8" module the image comes
+/// out shifted with a band of noise along two edges. `ST7735` adds the
+/// offsets to every window, so drawing coordinates always start at the
+/// visible corner. Most glass is centered in the RAM, see `centered`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Panel {
+    /// Visible width in pixels
+    pub width: u32,
+    /// Visible height in pixels
+    pub height: u32,
+    /// Controller RAM width, 132 or less depending on the GM strapping
+    pub ram_width: u32,
+    /// Controller RAM height, 162 or less depending on the GM strapping
+    pub ram_height: u32,
+    /// First RAM column that's visible
+    pub column_offset: u32,
+    /// First RAM row that's visible
+    pub row_offset: u32
+}
+
+impl Panel {
+
+    /// Largest RAM the controller has
+    pub const MAX_RAM_WIDTH: u32 = 132;
+    pub const MAX_RAM_HEIGHT: u32 = 162;
+
+    /// 1.8" 128x160 with a red or black tab, RAM strapped to the glass size
+    pub const RED_TAB_1_8: Panel = Panel::centered(128, 160, 128, 160);
+
+    /// 1.8" 128x160 with a green tab, glass at (2, 1)
+    pub const GREEN_TAB_1_8: Panel = Panel::centered(128, 160, 132, 162);
+
+    /// 1.44" 128x128, glass at (2, 3) of a 132x132 RAM
+    pub const GREEN_TAB_1_44: Panel = Panel {
+        width: 128,
+        height: 128,
+        ram_width: 132,
+        ram_height: 132,
+        column_offset: 2,
+        row_offset: 3
+    };
+
+    /// 0.96" 80x160, glass at (26, 1)
+    pub const MINI_0_96: Panel = Panel::centered(80, 160, 132, 162);
+
+    /// A `width`×`height` glass centered in a `ram_width`×`ram_height` RAM
+    ///
+    /// An odd margin leaves the extra pixel after the glass.
+    pub const fn centered(width: u32, height: u32, ram_width: u32, ram_height: u32) -> Self {
+        Panel {
+            width,
+            height,
+            ram_width,
+            ram_height,
+            column_offset: ram_width.saturating_sub(width) / 2,
+            row_offset: ram_height.saturating_sub(height) / 2
+        }
+    }
+
+    // Non-empty and within the RAM, which is within the controller
+    const fn is_valid(&self) -> bool {
+        self.width > 0 && self.height > 0
+            && self.ram_width <= Panel::MAX_RAM_WIDTH
+            && self.ram_height <= Panel::MAX_RAM_HEIGHT
+            && self.column_offset + self.width <= self.ram_width
+            && self.row_offset + self.height <= self.ram_height
+    }
+}
+
+impl Default for Panel {
+    fn default() -> Self {
+        Panel::RED_TAB_1_8
+    }
+}
+
 /*
     ST7735 Display
 
@@ -27,277 +237,1334 @@ pub enum ControlMode {
     NC |   |
     NC |   |
     CLK|PA5|SPI1_SCK
-    SDA|PA7|SPI1_MOSI
+    SDA|PA7|SPI1_MOSI (bidirectional for reads)
     RS |PA4|Data/Command select (GPIO)
     RST|PA1|Reset line (GPIO)
     CS |PA0|Chip Select (GPIO)
+
+    CS, RS and RST default to the pins above but can be moved to other GPIOA
+    pins with `ControlPins`
+
+    The driver itself only needs an embedded-hal `SpiBus<u8>` and `OutputPin`s
+    for CS, RS (D/C) and RST, see `ST7735::with_bus`. `ST7735::new` sets up
+    the wiring above with the PAC-backed implementations in `hal`. The
+    backlight (LED) is usually tied on; wired to a PWM output instead (e.g.
+    PA15 with `hal::Tim2Pwm`) it can be dimmed, see `with_backlight`.
 */
 
 pub trait Display {
 
     /// Setup and turn on the display
-    fn calibrate(&self);
+    fn calibrate(&self, delay: &mut impl DelayMs);
 
-    /// Fill in the display with a solid color
-    fn fill(&self, color: Option<u32>);
+    /// Fill in the display with a solid color, or clear it to black if `None`
+    fn fill(&self, color: Option<Rgb565>);
 
-    fn draw_row(&self, row: u32, buf: &[u16]);
+    /// Clear the display to black
+    fn clear(&self) {
+        self.fill(None);
+    }
+
+    /// Draw a line of pixels, see `AddressMode` for the axis it runs along
+    ///
+    /// Lines past the last panel row or column are ignored. Accepts RGB 565
+    /// (`Rgb565` or `u16`) and RGB 888 (`(u8, u8, u8)`) lines.
+    fn draw_row<P: IntoPanelColor>(&self, row: u32, buf: &[P]);
+
+    /// Push everything drawn since the last call to the panel
+    ///
+    /// Only needed in buffered mode, immediate mode draws go straight out.
+    fn present(&self) {}
 }
 
-pub struct ST7735<'a> {
-    spi: stm32f401::SPI1,
-    gpio: &'a stm32f401::GPIOA,
+pub struct ST7735<'a, SPI = Spi1<'a>, CS = GpioaPin<'a>, DC = GpioaPin<'a>, RST = GpioaPin<'a>> {
+    spi: RefCell<SPI>,
+    cs: RefCell<CS>,
+    dc: RefCell<DC>,
+    rst: RefCell<RST>,
     width: u32,
-    height: u32
+    height: u32,
+    offset: (u32, u32),
+    cs_dwell: u32,
+    timings: Cell<Timings>,
+    lut: Cell<ChannelLut>,
+    brightness: Cell<u8>,
+    channel_gains: Cell<(u8, u8, u8)>,
+    color_depth: Cell<ColorDepth>,
+    idle: Cell<bool>,
+    row_align: Cell<RowAlign>,
+    fit_mode: Cell<FitMode>,
+    address_mode: Cell<AddressMode>,
+    bus_fault: Cell<bool>,
+    buffer: RefCell<Option<&'a mut [Rgb565]>>,
+    buffered: Cell<bool>,
+    window: Cell<(u32, u32, u32, u32)>,
+    backlight: RefCell<Option<&'a mut (dyn SetDutyCycle<Error = Infallible> + Send)>>
 }
 
-impl<'a> Display for ST7735<'a> {
+impl<SPI, CS, DC, RST> Display for ST7735<'_, SPI, CS, DC, RST>
+where
+    SPI: SpiBus<u8>,
+    CS: OutputPin,
+    DC: OutputPin,
+    RST: OutputPin
+{
 
-    fn calibrate(&self) {
+    fn calibrate(&self, delay: &mut impl DelayMs) {
         const SWRESET: u8 = 0x01;
         const SLPOUT: u8 = 0x11;
         const DISPON: u8 = 0x29;
 
-        // TODO - replace display with one that supports:
-        // * Setting COLMOD to 16-bit RGB with a display that supports it
-        // * Clearing display ram before turning on display
-
         // CS not needed for hardware reset
         self.chip_select(PinState::Disable);
 
+        let timings = self.timings.get();
+
         // Reset display
         self.reset(PinState::Enable);
-        asm::delay(CLK_HZ / 1000 * 120); // ~120ms
+        delay.delay_ms(timings.reset_pulse_ms);
         self.reset(PinState::Disable);
-        asm::delay(CLK_HZ / 1000 * 120); // ~120ms
+        delay.delay_ms(timings.reset_ms);
 
-        self.chip_select(PinState::Enable);
+        self.with_cs(|| {
+            // Software reset
+            self.register_select(ControlMode::Command);
+            self.spi_write(&[SWRESET]);
+            delay.delay_ms(timings.swreset_ms);
 
-        // Software reset
-        self.register_select(ControlMode::Command);
-        self.spi_write(SWRESET);
-        asm::delay(CLK_HZ / 1000 * 120); // ~120ms
+            // Wake up display (from reset sleep)
+            self.spi_write(&[SLPOUT]);
+            delay.delay_ms(timings.slpout_ms);
+        });
 
-        // Wake up display (from reset sleep)
-        self.spi_write(SLPOUT);
-        asm::delay(CLK_HZ / 1000 * 120); // ~120ms
+        self.idle.set(false);
 
-        // Turn on the display
-        self.register_select(ControlMode::Command);
-        self.spi_write(DISPON);
-        asm::delay(CLK_HZ / 1000 * 120); // ~120ms
+        // Software reset restored the default color mode
+        if self.color_depth.get() != ColorDepth::Bits18 {
+            self.set_color_depth(self.color_depth.get());
+        }
 
-        // Clear display
-        self.fill(None);
+        // Display RAM is undefined after reset, blank it before it's shown
+        self.clear();
+
+        self.with_cs(|| {
+            // Turn on the display
+            self.register_select(ControlMode::Command);
+            self.spi_write(&[DISPON]);
+            delay.delay_ms(timings.dispon_ms);
+        });
     }
 
-    fn fill(&self, color: Option<u32>) {
+    fn fill(&self, color: Option<Rgb565>) {
+        self.fast_fill(color.unwrap_or(Rgb565::BLACK));
+    }
 
-        const CASET: u8 = 0x2A;
-        const RASET: u8 = 0x2B;
-        const RAMWR: u8 = 0x2C;
-        const NOP: u8 = 0x00;
+    fn draw_row<P: IntoPanelColor>(&self, row: u32, buf: &[P]) {
+        self.draw_rows(row, buf, 1);
+    }
 
-        const WHITE: u32 = 0xFFFFFF;
+    fn present(&self) {
 
-        let color = color.unwrap_or(WHITE);
+        if !self.buffered.get() {
+            return;
+        }
 
-        self.chip_select(PinState::Enable);
+        let buffer = self.buffer.borrow();
 
-        // Draw sequence fails without this
-        self.register_select(ControlMode::Command);
-        self.spi_write(NOP);
+        let Some(buffer) = buffer.as_deref() else {
+            return;
+        };
 
-        // Set column range
-        self.register_select(ControlMode::Command);
-        self.spi_write(CASET);
-        self.register_select(ControlMode::Data);
-        // Set x0
-        self.spi_write(0x00); // MSB
-        self.spi_write(0x00); // LSB
-        // Set x1
-        self.spi_write(0x00); // MSB
-        self.spi_write((self.width - 1) as u8); // LSB
-
-        // Set row range
-        self.register_select(ControlMode::Command);
-        self.spi_write(RASET);
-        self.register_select(ControlMode::Data);
-        // Set y0
-        self.spi_write(0x00); // MSB
-        self.spi_write(0x00); // LSB
-        // Set y1
-        self.spi_write(0x00); // MSB
-        self.spi_write((self.height - 1) as u8); // LSB
+        self.with_cs(|| {
+            self.send_window(0, 0, self.width - 1, self.height - 1);
+            self.send_pixels(buffer[..(self.width * self.height) as usize].iter().copied());
+        });
+    }
+}
 
-        // Write to the display
-        self.register_select(ControlMode::Command);
-        self.spi_write(RAMWR);
-        self.register_select(ControlMode::Data);
+impl<'a> ST7735<'a> {
+
+    /// Set up SPI1 and the GPIOA control pins and build the driver on them
+    pub fn new(
+        rcc: &stm32f401::RCC,
+        gpioa: &'a stm32f401::GPIOA,
+        spi1: &'a stm32f401::SPI1,
+        pins: ControlPins,
+        panel: Panel,
+        config: &Config
+    ) -> Result<Self, InitError> {
+
+        // Control pins must be distinct GPIOA pins clear of SPI1_SCK/SPI1_MOSI
+        let control = [pins.cs, pins.rs, pins.rst];
+        if control.iter().any(|&pin| pin >= 16 || pin == 5 || pin == 7)
+            || pins.cs == pins.rs || pins.cs == pins.rst || pins.rs == pins.rst {
+            return Err(InitError::InvalidConfig);
+        }
+
+        let cs = GpioaPin::new(rcc, gpioa, pins.cs)?;
+        let dc = GpioaPin::new(rcc, gpioa, pins.rs)?;
+        let rst = GpioaPin::new(rcc, gpioa, pins.rst)?;
+
+        let spi = Spi1::new(rcc, gpioa, spi1, config)?;
+
+        // SPI1 runs off the core clock, so the divider is the bit period in cycles
+        ST7735::with_bus(spi, cs, dc, rst, panel, config.spi_divider)
+    }
+
+    /// Check that the SPI peripheral has no pending mode-fault or overrun errors
+    pub fn spi_status_ok(&self) -> bool {
+        self.spi.borrow().status_ok()
+    }
+}
+
+// Bytes per bus write when streaming pixels, a whole number of pixels at
+// every color depth
+const BLOCK_LEN: usize = 60;
+
+// RAM access order left by SWRESET: top to bottom, left to right, RGB
+const MADCTL_DEFAULT: u8 = 0x00;
+
+// Line pixel under panel pixel `i`, for a span from `line_span`, or black for
+// the padding either side of it
+fn span_pixel<P: IntoPanelColor>(line: &[P], i: u32, span: (u32, u32, Option<usize>), extent: u32) -> P {
+
+    let (first, length, skip) = span;
+
+    if i < first || i >= first + length {
+        return P::BLACK;
+    }
+
+    let i = (i - first) as usize;
+    let x = match skip {
+        Some(skip) => skip + i,
+        // Pick the source pixel under each panel pixel
+        None => i * line.len() / extent as usize
+    };
+    line[x]
+}
+
+impl<'a, SPI, CS, DC, RST> ST7735<'a, SPI, CS, DC, RST>
+where
+    SPI: SpiBus<u8>,
+    CS: OutputPin,
+    DC: OutputPin,
+    RST: OutputPin
+{
+
+    /// Build the driver on an already configured SPI bus and control pins
+    ///
+    /// The bus must run SPI mode 0 with 8-bit words. `cs_dwell` is how long
+    /// CS is held either side of a transfer, in spin-loop iterations; about
+    /// one SPI bit period keeps the first and last bits from being clipped.
+    pub fn with_bus(
+        spi: SPI,
+        mut cs: CS,
+        dc: DC,
+        rst: RST,
+        panel: Panel,
+        cs_dwell: u32
+    ) -> Result<Self, InitError> {
+
+        // Panel must fit within the controller RAM
+        if !panel.is_valid() {
+            return Err(InitError::InvalidConfig);
+        }
+
+        // Deselect until the first transfer, another panel may share the bus
+        let _ = cs.set_high();
+
+        Ok(ST7735 {
+            spi: RefCell::new(spi),
+            cs: RefCell::new(cs),
+            dc: RefCell::new(dc),
+            rst: RefCell::new(rst),
+            width: panel.width,
+            height: panel.height,
+            offset: (panel.column_offset, panel.row_offset),
+            cs_dwell,
+            timings: Cell::new(Timings::DEFAULT),
+            lut: Cell::new(ChannelLut::new(u8::MAX)),
+            brightness: Cell::new(u8::MAX),
+            channel_gains: Cell::new((ChannelLut::UNITY_GAIN, ChannelLut::UNITY_GAIN, ChannelLut::UNITY_GAIN)),
+            color_depth: Cell::new(ColorDepth::Bits18),
+            idle: Cell::new(false),
+            row_align: Cell::new(RowAlign::Start),
+            fit_mode: Cell::new(FitMode::Crop),
+            address_mode: Cell::new(AddressMode::ColumnMajor),
+            bus_fault: Cell::new(false),
+            buffer: RefCell::new(None),
+            buffered: Cell::new(false),
+            window: Cell::new((0, 0, 0, 0)),
+            backlight: RefCell::new(None)
+        })
+    }
+
+    /// Attach a panel-sized working buffer for buffered mode
+    ///
+    /// `buffer` holds the panel row-major and must cover `width × height`
+    /// pixels, 40KB for a 128x160 panel, so keep it in a `static`.
+    pub fn with_buffer(self, buffer: &'a mut [Rgb565]) -> Result<Self, InitError> {
+
+        if buffer.len() < (self.width * self.height) as usize {
+            return Err(InitError::InvalidConfig);
+        }
+
+        *self.buffer.borrow_mut() = Some(buffer);
+
+        Ok(self)
+    }
+
+    /// Attach a PWM output driving the backlight, for `set_brightness`
+    pub fn with_backlight(self, backlight: &'a mut (dyn SetDutyCycle<Error = Infallible> + Send)) -> Self {
+        *self.backlight.borrow_mut() = Some(backlight);
+        self
+    }
+
+    /// Switch between immediate and buffered drawing
+    ///
+    /// In buffered mode every draw call updates the working buffer instead of
+    /// the panel, and `present` sends the whole buffer in one go, so overlays
+    /// drawn on top of a frame never flicker. Brightness and gains are applied
+    /// by `present`. The buffer is RGB 565, so RGB 888 draws are truncated to
+    /// it. Ignored without a buffer, see `with_buffer`.
+    pub fn set_buffered(&self, on: bool) {
+        self.buffered.set(on && self.buffer.borrow().is_some());
+    }
+
+    /// Draw a w×h image with its top-left corner at (x, y)
+    ///
+    /// The image is clipped to the panel bounds. `data` that isn't exactly
+    /// w×h pixels draws nothing. See `IntoPanelColor` for the accepted pixel
+    /// formats.
+    pub fn draw_image<P: IntoPanelColor>(&self, x: u32, y: u32, w: u32, h: u32, data: &[P]) {
+
+        // Nothing is drawn unless `data` is exactly w×h pixels
+        if w.checked_mul(h) != u32::try_from(data.len()).ok() {
+            return;
+        }
+
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        let visible_w = w.min(self.width - x);
+        let visible_h = h.min(self.height - y);
+
+        if visible_w == 0 || visible_h == 0 {
+            return;
+        }
+
+        self.with_cs(|| {
+            self.set_window(x, y, x + visible_w - 1, y + visible_h - 1);
+
+            // Stream the visible part of each image row
+            self.write_pixels((0..visible_h).flat_map(|row| {
+                let start = (row * w) as usize;
+                data[start..start + visible_w as usize].iter().copied()
+            }));
+        });
+    }
+
+    /// Draw `count` lines from `start`, packed back to back in `buf`
+    ///
+    /// Like `draw_row` for each line, but the lines share one window and one
+    /// RAMWR instead of a window setup per line, so a frame already in RAM
+    /// goes out with a handful of commands. Nothing is drawn unless `buf`
+    /// holds `count` lines of equal length; lines past the last panel row or
+    /// column are ignored.
+    pub fn draw_rows<P: IntoPanelColor>(&self, start: u32, buf: &[P], count: u32) {
+
+        if count == 0 || buf.is_empty() || !buf.len().is_multiple_of(count as usize) {
+            return;
+        }
+
+        let mode = self.address_mode.get();
+        let (extent, lines) = self.line_extent();
+
+        // Lines past the panel edge would land outside the visible area
+        if start >= lines {
+            return;
+        }
 
-        // Fill in display
-        for _ in 0..self.height {
-            for _ in 0..self.width {
-                self.spi_write(((color >> 16) & 0xFF) as u8); // R
-                self.spi_write(((color >> 8) & 0xFF) as u8); // G
-                self.spi_write((color & 0xFF) as u8); // B
+        let line_len = buf.len() / count as usize;
+        let count = count.min(lines - start);
+
+        let span = self.line_span(line_len, extent);
+
+        // Line pixel under panel pixel `i` of `line`
+        let pixel = move |line: u32, i: u32| {
+            let offset = line as usize * line_len;
+            span_pixel(&buf[offset..offset + line_len], i, span, extent)
+        };
+
+        // Lines cover the whole panel axis so the padding is drawn too
+        let last = extent - 1;
+        let end = start + count - 1;
+
+        self.with_cs(|| match mode {
+            AddressMode::RowMajor => {
+                self.set_window(0, start, last, end);
+                self.write_pixels((0..count).flat_map(|line| (0..extent).map(move |i| pixel(line, i))));
             }
+            // RAM fills along panel rows, so send the lines interleaved
+            AddressMode::ColumnMajor => {
+                self.set_window(start, 0, end, last);
+                self.write_pixels((0..extent).flat_map(|i| (0..count).map(move |line| pixel(line, i))));
+            }
+        });
+    }
+
+    /// Draw `count` lines from `start`, each filled into `line` by
+    /// `next_line` just before it's sent
+    ///
+    /// For lines produced one at a time, e.g. captured from the camera: the
+    /// frame shares one window and one RAMWR like `draw_rows`, without
+    /// holding every line in RAM. `next_line` is called for all `count`
+    /// lines, lines past the panel edge are captured but not drawn. Stops
+    /// at the first error from `next_line` and returns it, the lines already
+    /// sent stay on the panel.
+    pub fn stream_rows<P: IntoPanelColor, E>(
+        &self,
+        start: u32,
+        count: u32,
+        line: &mut [P],
+        mut next_line: impl FnMut(&mut [P]) -> Result<(), E>
+    ) -> Result<(), E> {
+
+        let (extent, lines) = self.line_extent();
+        let visible = count.min(lines.saturating_sub(start));
+
+        let span = self.line_span(line.len(), extent);
+
+        // RGB 444 packs pixel pairs, so an odd line would leave half a byte
+        // between lines sent separately into one window. The buffer has no
+        // RAMWR to save
+        let split = self.buffered.get()
+            || (self.color_depth.get() == ColorDepth::Bits12 && extent % 2 == 1);
+
+        if visible == 0 || line.is_empty() || split {
+            return (0..count).try_for_each(|n| {
+                next_line(line)?;
+                self.draw_rows(start + n, line, 1);
+                Ok(())
+            });
         }
 
-        self.register_select(ControlMode::Command);
-        self.chip_select(PinState::Disable);
+        let mode = self.address_mode.get();
+
+        // Lines cover the whole panel axis so the padding is drawn too
+        let last = extent - 1;
+        let end = start + visible - 1;
+
+        self.with_cs(|| {
+            match mode {
+                AddressMode::RowMajor => self.send_window(0, start, last, end),
+                AddressMode::ColumnMajor => self.send_window_transposed(start, 0, end, last)
+            }
+
+            let result = (0..count).try_for_each(|n| {
+                next_line(line)?;
+                if n < visible {
+                    let line = &*line;
+                    self.send_pixels((0..extent).map(|i| span_pixel(line, i, span, extent)));
+                }
+                Ok(())
+            });
+
+            if mode == AddressMode::ColumnMajor {
+                self.send_memory_order(MADCTL_DEFAULT);
+            }
+
+            result
+        })
     }
 
-    // Note: drawing camera "row" here to LCD col since LCD has longer vertical
-    fn draw_row(&self, row: u32, buf: &[u16]) {
+    /// Fill a w×h rectangle with its top-left corner at (x, y) with a solid color
+    ///
+    /// The rectangle is clipped to the panel bounds.
+    pub fn fill_rect(&self, x: u32, y: u32, w: u32, h: u32, color: Rgb565) {
 
-        const CASET: u8 = 0x2A;
-        const RASET: u8 = 0x2B;
-        const RAMWR: u8 = 0x2C;
-        const NOP: u8 = 0x00;
+        if x >= self.width || y >= self.height {
+            return;
+        }
 
-        let length = self.width.min(buf.len().try_into().unwrap());
+        let visible_w = w.min(self.width - x);
+        let visible_h = h.min(self.height - y);
 
-        if length == 0 {
+        if visible_w == 0 || visible_h == 0 {
             return;
         }
 
-        self.chip_select(PinState::Enable);
+        self.with_cs(|| {
+            self.set_window(x, y, x + visible_w - 1, y + visible_h - 1);
+            self.write_pixels(core::iter::repeat_n(color, (visible_w * visible_h) as usize));
+        });
+    }
 
-        // Draw sequence fails without this
-        self.register_select(ControlMode::Command);
-        self.spi_write(NOP);
+    /// Fill the whole panel with a solid color as fast as the bus allows
+    ///
+    /// The color is converted once and repeated through a block of
+    /// precomputed transfer bytes, so the bus sees back-to-back blocks instead
+    /// of a conversion and a write call per pixel. `fill` and `clear` go
+    /// through here.
+    pub fn fast_fill(&self, color: Rgb565) {
+
+        if self.buffered.get() {
+            self.fill_rect(0, 0, self.width, self.height, color);
+            return;
+        }
+
+        let (red, green, blue) = color.into_panel(&self.lut.get());
+
+        // Transfer bytes for a run of pixels, their length and pixel count
+        let (pattern, pattern_len, pattern_pixels) = match self.color_depth.get() {
+            ColorDepth::Bits12 => {
+                let packed = ((red & 0xF0) as u16) << 4 | (green & 0xF0) as u16 | (blue >> 4) as u16;
+                ([(packed >> 4) as u8, ((packed << 4) as u8 & 0xF0) | (packed >> 8) as u8, packed as u8], 3, 2)
+            }
+            ColorDepth::Bits16 => {
+                let [high, low] = Rgb565::from_rgb888(red, green, blue).0.to_be_bytes();
+                ([high, low, 0], 2, 1)
+            }
+            ColorDepth::Bits18 => ([red, green, blue], 3, 1)
+        };
+
+        let mut block = [0; BLOCK_LEN];
+        for (i, byte) in block.iter_mut().enumerate() {
+            *byte = pattern[i % pattern_len];
+        }
+
+        let block_pixels = BLOCK_LEN / pattern_len * pattern_pixels;
+        let total = (self.width * self.height) as usize;
+
+        self.with_cs(|| {
+            self.send_window(0, 0, self.width - 1, self.height - 1);
+
+            {
+                let mut spi = self.spi.borrow_mut();
+                for _ in 0..total / block_pixels {
+                    self.record(spi.write(&block));
+                }
+            }
+
+            // Leftover pixels, packed like any other draw
+            self.send_pixels(core::iter::repeat_n(color, total % block_pixels));
+        });
+    }
+
+    /// Set a single pixel, ignoring points outside the panel
+    pub fn draw_pixel(&self, x: u32, y: u32, color: Rgb565) {
+        self.fill_rect(x, y, 1, 1, color);
+    }
+
+    /// Draw a horizontal line of `length` pixels starting at (x, y)
+    pub fn draw_hline(&self, x: u32, y: u32, length: u32, color: Rgb565) {
+        self.fill_rect(x, y, length, 1, color);
+    }
+
+    /// Draw a vertical line of `length` pixels starting at (x, y)
+    pub fn draw_vline(&self, x: u32, y: u32, length: u32, color: Rgb565) {
+        self.fill_rect(x, y, 1, length, color);
+    }
+
+    /// Draw a line from (x0, y0) to (x1, y1) inclusive
+    pub fn draw_line(&self, x0: u32, y0: u32, x1: u32, y1: u32, color: Rgb565) {
+
+        // Axis-aligned lines can be drawn as a single window
+        if y0 == y1 {
+            self.draw_hline(x0.min(x1), y0, x0.abs_diff(x1) + 1, color);
+            return;
+        }
 
-        // Set column range
+        if x0 == x1 {
+            self.draw_vline(x0, y0.min(y1), y0.abs_diff(y1) + 1, color);
+            return;
+        }
+
+        // Bresenham's line algorithm
+        let (mut x, mut y) = (x0 as i32, y0 as i32);
+        let (x1, y1) = (x1 as i32, y1 as i32);
+
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let step_x = if x < x1 { 1 } else { -1 };
+        let step_y = if y < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+
+        loop {
+            self.draw_pixel(x as u32, y as u32, color);
+
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let err2 = 2 * err;
+
+            if err2 >= dy {
+                err += dy;
+                x += step_x;
+            }
+
+            if err2 <= dx {
+                err += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Draw the 1px outline of a w×h rectangle with its top-left corner at (x, y)
+    pub fn draw_rect_outline(&self, x: u32, y: u32, w: u32, h: u32, color: Rgb565) {
+
+        if w == 0 || h == 0 {
+            return;
+        }
+
+        self.draw_hline(x, y, w, color); // Top
+        self.draw_hline(x, y + h - 1, w, color); // Bottom
+        self.draw_vline(x, y, h, color); // Left
+        self.draw_vline(x + w - 1, y, h, color); // Right
+    }
+
+    /// Draw vertical color bars over a grayscale ramp
+    ///
+    /// Needs nothing but the panel, so display wiring and color handling can
+    /// be checked without a camera. The bars run white, yellow, cyan, green,
+    /// magenta, red, blue left to right over the top three quarters, and the
+    /// ramp goes from black to white along the bottom quarter.
+    pub fn draw_test_pattern(&self) {
+
+        const BARS: [Rgb565; 7] = [
+            Rgb565::WHITE,
+            Rgb565::YELLOW,
+            Rgb565::CYAN,
+            Rgb565::GREEN,
+            Rgb565::MAGENTA,
+            Rgb565::RED,
+            Rgb565::BLUE
+        ];
+
+        let bars_h = self.height * 3 / 4;
+
+        // Spread any leftover columns over the bars instead of leaving a gap
+        for (i, &color) in BARS.iter().enumerate() {
+            let x0 = self.width * i as u32 / BARS.len() as u32;
+            let x1 = self.width * (i as u32 + 1) / BARS.len() as u32;
+            self.fill_rect(x0, 0, x1 - x0, bars_h, color);
+        }
+
+        for x in 0..self.width {
+            let level = (x * 255 / (self.width - 1).max(1)) as u8;
+            self.draw_vline(x, bars_h, self.height - bars_h, Rgb565::from_rgb888(level, level, level));
+        }
+    }
+
+    /// Draw a 1px white grid every `spacing` pixels inside a red panel border
+    ///
+    /// Makes addressing faults obvious: a column or row offset (e.g. the
+    /// green-tab panels' RAM offset) cuts off or shifts the red border, a
+    /// dropped or duplicated column bends the grid lines, and swapped rows
+    /// and columns turn the spacing around. A `spacing` of 0 draws only the
+    /// border.
+    pub fn draw_calibration_grid(&self, spacing: u32) {
+
+        self.clear();
+
+        if spacing > 0 {
+            for x in (0..self.width).step_by(spacing as usize) {
+                self.draw_vline(x, 0, self.height, Rgb565::WHITE);
+            }
+
+            for y in (0..self.height).step_by(spacing as usize) {
+                self.draw_hline(0, y, self.width, Rgb565::WHITE);
+            }
+        }
+
+        self.draw_rect_outline(0, 0, self.width, self.height, Rgb565::RED);
+    }
+
+    /// Set the backlight to `percent` (0-100) of full perceived brightness
+    ///
+    /// The eye's response to light is far from linear, so the duty cycle
+    /// follows the CIE 1976 lightness curve: 50% is about 18% duty, and
+    /// looks half as bright as 100%. Returns false, doing nothing, without a
+    /// backlight (see `with_backlight`); use `set_software_brightness` then.
+    pub fn set_brightness(&self, percent: u8) -> bool {
+
+        // Linear light output, 0-65535, for each perceived percent
+        const LUT: [u16; 101] = perceived_brightness_lut();
+
+        let mut backlight = self.backlight.borrow_mut();
+
+        let Some(backlight) = backlight.as_deref_mut() else {
+            return false;
+        };
+
+        let max = backlight.max_duty_cycle() as u32;
+        let duty = LUT[percent.min(100) as usize] as u32 * max / u16::MAX as u32;
+        let _ = backlight.set_duty_cycle(duty as u16);
+
+        true
+    }
+
+    /// Set the backlight PWM duty directly, up to the output's maximum
+    ///
+    /// Returns false, doing nothing, without a backlight (see
+    /// `with_backlight`).
+    pub fn set_brightness_raw(&self, duty: u16) -> bool {
+
+        let mut backlight = self.backlight.borrow_mut();
+
+        let Some(backlight) = backlight.as_deref_mut() else {
+            return false;
+        };
+
+        let _ = backlight.set_duty_cycle(duty.min(backlight.max_duty_cycle()));
+
+        true
+    }
+
+    /// Dim everything drawn from now on by `level / 255` in software
+    ///
+    /// For panels whose backlight isn't controllable by the MCU. 255 is full
+    /// brightness and costs nothing beyond the table lookup every pixel pays.
+    pub fn set_software_brightness(&self, level: u8) {
+        self.brightness.set(level);
+        self.lut.set(ChannelLut::with_gains(level, self.channel_gains.get()));
+    }
+
+    /// Correct a color cast in software by scaling each channel by gain / 128
+    ///
+    /// 128 leaves a channel unchanged, higher values boost it (saturating).
+    /// Applied in the same lookup as `set_software_brightness`, so it costs
+    /// nothing extra per pixel and works without touching the sensor.
+    pub fn set_channel_gains(&self, red: u8, green: u8, blue: u8) {
+        self.channel_gains.set((red, green, blue));
+        self.lut.set(ChannelLut::with_gains(self.brightness.get(), (red, green, blue)));
+    }
+
+    /// Select the panel axis `draw_row` lines run along
+    pub fn set_address_mode(&self, mode: AddressMode) {
+        self.address_mode.set(mode);
+    }
+
+    /// Visible width in pixels, from the `Panel` passed to `new`
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Visible height in pixels, from the `Panel` passed to `new`
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Panel (width, height) in pixels
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Override the bring-up delays used by the next `calibrate`
+    pub fn set_timings(&self, timings: Timings) {
+        self.timings.set(timings);
+    }
+
+    /// Select how `draw_row` clips or pads lines that don't fit the panel
+    pub fn set_row_align(&self, align: RowAlign) {
+        self.row_align.set(align);
+    }
+
+    /// Select how `draw_row` fits lines to the panel, see `FitMode`
+    pub fn set_fit_mode(&self, mode: FitMode) {
+        self.fit_mode.set(mode);
+    }
+
+    /// Select the pixel format used by the draw paths
+    ///
+    /// Programs COLMOD and switches the pixel packing to match. 16-bit cuts
+    /// the per-pixel transfer overhead of the 18-bit default, 12-bit cuts it
+    /// further at reduced color fidelity. Requires a panel that accepts COLMOD.
+    pub fn set_color_depth(&self, depth: ColorDepth) {
+
+        const COLMOD: u8 = 0x3A;
+        const COLMOD_12BIT: u8 = 0x03;
+        const COLMOD_16BIT: u8 = 0x05;
+        const COLMOD_18BIT: u8 = 0x06;
+
+        self.with_cs(|| {
+            self.register_select(ControlMode::Command);
+            self.spi_write(&[COLMOD]);
+            self.register_select(ControlMode::Data);
+            self.spi_write(&[match depth {
+                ColorDepth::Bits12 => COLMOD_12BIT,
+                ColorDepth::Bits16 => COLMOD_16BIT,
+                ColorDepth::Bits18 => COLMOD_18BIT
+            }]);
+        });
+
+        self.color_depth.set(depth);
+    }
+
+    /// Switch idle mode on or off (IDMON/IDMOFF)
+    ///
+    /// Idle mode drops the panel to 8 colors, keeping only the top bit of
+    /// each channel, and lowers its power draw. Suits a static low-color
+    /// image shown between captures; drawing continues to work as normal.
+    pub fn set_idle_mode(&self, on: bool) {
+
+        const IDMOFF: u8 = 0x38;
+        const IDMON: u8 = 0x39;
+
+        self.with_cs(|| {
+            self.register_select(ControlMode::Command);
+            self.spi_write(&[if on { IDMON } else { IDMOFF }]);
+        });
+
+        self.idle.set(on);
+    }
+
+    /// Whether idle mode is on, see `set_idle_mode`
+    pub fn idle_mode(&self) -> bool {
+        self.idle.get()
+    }
+
+    /// Report whether any bus write failed since the last check
+    ///
+    /// Draw calls keep going past bus errors (the bus clears transient
+    /// faults itself) so one glitch only costs a few pixels. Call this once
+    /// per frame to catch faults that persist, e.g. to reinitialize the
+    /// display. Clears the fault.
+    pub fn check_bus(&self) -> Result<(), DisplayError> {
+        match self.bus_fault.replace(false) {
+            true => Err(DisplayError::Bus),
+            false => Ok(())
+        }
+    }
+
+    /// Read the 24-bit display ID (RDDID)
+    ///
+    /// Returns the manufacturer, module/driver version and module/driver ID
+    /// bytes. The ST7735 answers on its bidirectional SDA line, so the bus must
+    /// be able to read on that line (`Spi1` turns it around with BIDIMODE).
+    pub fn read_id(&self) -> Result<[u8; 3], DisplayError> {
+
+        const RDDID: u8 = 0x04;
+
+        // One dummy clock precedes the 24 ID bits
+        let mut raw = [0; 4];
+        self.read_command(RDDID, &mut raw)?;
+
+        let bits = u32::from_be_bytes(raw) << 1;
+
+        Ok([(bits >> 24) as u8, (bits >> 16) as u8, (bits >> 8) as u8])
+    }
+
+    /// Read the panel's power and display state (RDDST)
+    ///
+    /// Confirms the panel actually accepted SLPOUT, DISPON and COLMOD rather
+    /// than assuming it did. Reads back on SDA like `read_id`.
+    pub fn read_status(&self) -> Result<DisplayStatus, DisplayError> {
+
+        const RDDST: u8 = 0x09;
+
+        const BSTON: u32 = 1 << 31;
+        const IFPF_SHIFT: u32 = 20;
+        const IFPF_MASK: u32 = 0x07;
+        const IDMON: u32 = 1 << 19;
+        const SLOUT: u32 = 1 << 17;
+        const INVON: u32 = 1 << 13;
+        const DISON: u32 = 1 << 10;
+
+        // One dummy clock precedes the 32 status bits
+        let mut raw = [0; 5];
+        self.read_command(RDDST, &mut raw)?;
+
+        let bits = (u64::from_be_bytes([0, 0, 0, raw[0], raw[1], raw[2], raw[3], raw[4]]) >> 7) as u32;
+
+        Ok(DisplayStatus {
+            booster_on: bits & BSTON != 0,
+            sleeping: bits & SLOUT == 0,
+            idle: bits & IDMON != 0,
+            inverted: bits & INVON != 0,
+            display_on: bits & DISON != 0,
+            color_depth: match (bits >> IFPF_SHIFT) & IFPF_MASK {
+                0b011 => Some(ColorDepth::Bits12),
+                0b101 => Some(ColorDepth::Bits16),
+                0b110 => Some(ColorDepth::Bits18),
+                _ => None
+            }
+        })
+    }
+
+    // Panel pixels along a `draw_row` line, and lines across the panel
+    fn line_extent(&self) -> (u32, u32) {
+        match self.address_mode.get() {
+            AddressMode::RowMajor => (self.width, self.height),
+            AddressMode::ColumnMajor => (self.height, self.width)
+        }
+    }
+
+    // Where a line of `line_len` pixels lands along a panel line of `extent`
+    // pixels: the first panel pixel drawn, how many, and the first line pixel
+    // drawn (or `None` to resample the line to the panel)
+    fn line_span(&self, line_len: usize, extent: u32) -> (u32, u32, Option<usize>) {
+
+        let fit_mode = self.fit_mode.get();
+
+        if fit_mode == FitMode::Scale {
+            return (0, extent, None);
+        }
+
+        // Clip in usize so no buffer length can overflow, the clipped length
+        // is at most `extent` so it always fits back in a u32
+        let clipped = line_len.min(extent as usize);
+        let length = clipped as u32;
+
+        let spare = extent - length; // Unused panel pixels
+        let excess = line_len - clipped; // Clipped line pixels
+
+        let align = match fit_mode {
+            FitMode::Center => RowAlign::Center,
+            _ => self.row_align.get()
+        };
+
+        match align {
+            RowAlign::Start => (0, length, Some(0)),
+            RowAlign::Center => (spare / 2, length, Some(excess / 2)),
+            RowAlign::End => (spare, length, Some(excess))
+        }
+    }
+
+    // Select the inclusive window (x0, y0)..(x1, y1) for the next `write_pixels`
+    // On the panel, or in the working buffer in buffered mode
+    fn set_window(&self, x0: u32, y0: u32, x1: u32, y1: u32) {
+        match self.buffered.get() {
+            true => self.window.set((x0, y0, x1, y1)),
+            false => self.send_window(x0, y0, x1, y1)
+        }
+    }
+
+    // Fill the window selected by `set_window` like the panel's RAM write:
+    // left to right, then top to bottom
+    fn write_pixels<P: IntoPanelColor>(&self, pixels: impl Iterator<Item = P>) {
+
+        if !self.buffered.get() {
+            self.send_pixels(pixels);
+            return;
+        }
+
+        let mut buffer = self.buffer.borrow_mut();
+
+        let Some(buffer) = buffer.as_deref_mut() else {
+            return;
+        };
+
+        let (x0, y0, x1, y1) = self.window.get();
+        let (mut x, mut y) = (x0, y0);
+
+        for color in pixels {
+
+            if y > y1 {
+                break;
+            }
+
+            if let Some(pixel) = buffer.get_mut((y * self.width + x) as usize) {
+                *pixel = color.into_rgb565();
+            }
+
+            x += 1;
+            if x > x1 {
+                x = x0;
+                y += 1;
+            }
+        }
+    }
+
+    // Select the inclusive panel window (x0, y0)..(x1, y1) and start a RAM write
+    // Leaves the display in data mode, ready to receive pixels
+    fn send_window(&self, x0: u32, y0: u32, x1: u32, y1: u32) {
+
+        // Panel coordinates start at the visible corner of the RAM
+        let (column, row) = self.offset;
+
+        // No leading NOP needed: D/C and CS changes wait for the bus to drain,
+        // so they no longer land mid-byte and clip the trailing byte of the
+        // previous transfer
+        self.send_ranges((x0 + column, x1 + column), (y0 + row, y1 + row));
+    }
+
+    // Like `send_window`, but with rows and columns exchanged (MADCTL MV) so
+    // RAM fills down each panel column of the window in turn, then left to
+    // right. The caller restores the order with `MADCTL_DEFAULT`
+    fn send_window_transposed(&self, x0: u32, y0: u32, x1: u32, y1: u32) {
+
+        const MADCTL_MV: u8 = 0x20;
+
+        let (column, row) = self.offset;
+
+        self.send_memory_order(MADCTL_MV);
+
+        // The column address now runs down the panel and the row address across
+        self.send_ranges((y0 + row, y1 + row), (x0 + column, x1 + column));
+    }
+
+    // Set the RAM column and row address ranges, inclusive, and start a RAM write
+    fn send_ranges(&self, (c0, c1): (u32, u32), (r0, r1): (u32, u32)) {
+
+        const CASET: u8 = 0x2A;
+        const RASET: u8 = 0x2B;
+        const RAMWR: u8 = 0x2C;
+
+        // Set column range, c0 then c1 MSB first
         self.register_select(ControlMode::Command);
-        self.spi_write(CASET);
+        self.spi_write(&[CASET]);
         self.register_select(ControlMode::Data);
-        // Set x0
-        self.spi_write(0x00); // MSB
-        self.spi_write(row as u8); // LSB
-        // Set x1
-        self.spi_write(0x00); // MSB
-        self.spi_write(row as u8); // LSB
-
-        // Set row range
+        self.spi_write(&[(c0 >> 8) as u8, c0 as u8, (c1 >> 8) as u8, c1 as u8]);
+
+        // Set row range, r0 then r1 MSB first
         self.register_select(ControlMode::Command);
-        self.spi_write(RASET);
+        self.spi_write(&[RASET]);
         self.register_select(ControlMode::Data);
-        // Set y0
-        self.spi_write(0x00); // MSB
-        self.spi_write(0x00 as u8); // LSB
-        // Set y1
-        self.spi_write(0x00); // MSB
-        self.spi_write((length - 1) as u8); // LSB
+        self.spi_write(&[(r0 >> 8) as u8, r0 as u8, (r1 >> 8) as u8, r1 as u8]);
 
         // Write to the display
         self.register_select(ControlMode::Command);
-        self.spi_write(RAMWR);
+        self.spi_write(&[RAMWR]);
         self.register_select(ControlMode::Data);
+    }
 
-        // Fill in display
-        for i in 0..length {
-            let color = buf[i as usize];
+    // Set the RAM access order (MADCTL)
+    fn send_memory_order(&self, madctl: u8) {
 
-            // Convert RGB 565 to RGB 888
-            let red = ((color >> 11) & 0x1F) << 3;
-            let green = ((color >> 5)  & 0x3F) << 2;
-            let blue = (color & 0x1F) << 3;
-
-            self.spi_write(red as u8);
-            self.spi_write(green as u8);
-            self.spi_write(blue as u8);
-        }
+        const MADCTL: u8 = 0x36;
 
         self.register_select(ControlMode::Command);
-        self.chip_select(PinState::Disable);
+        self.spi_write(&[MADCTL]);
+        self.register_select(ControlMode::Data);
+        self.spi_write(&[madctl]);
     }
-}
 
-impl<'a> ST7735<'a> {
+    // Stream pixels into the current panel window in the active transfer format
+    fn send_pixels<P: IntoPanelColor>(&self, pixels: impl Iterator<Item = P>) {
 
-    pub fn new(
-        rcc: &stm32f401::RCC,
-        gpioa: &'a stm32f401::GPIOA,
-        spi1: stm32f401::SPI1,
-        width: u32,
-        height: u32
-    ) -> Self {
-
-        // Enable GPIOA clock
-        rcc.ahb1enr.modify(|_, w| w.gpioaen().enabled());
-
-        // Configure output pins
-        gpioa.moder.modify(|_, w| {
-            w.moder0().output() // CS
-             .moder1().output() // RST
-             .moder4().output() // RS
-        });
+        let lut = self.lut.get();
 
-        // Enable SPI1 clock
-        rcc.apb2enr.modify(|_, w| w.spi1en().enabled());
+        // Hold the bus for the whole stream rather than borrowing per pixel
+        let mut spi = self.spi.borrow_mut();
 
-        // Configure SPI pins
-        gpioa.moder.modify(|_, w| {
-            w.moder5().alternate() // CLK
-             .moder7().alternate() // SDA
-        });
+        // Pixels are packed into a block and sent a block per write, so the
+        // bus isn't called (and checked) once per pixel
+        let mut block = [0; BLOCK_LEN];
+        let mut len = 0;
 
-        // Set SPI pin alternate functions
-        gpioa.afrl.modify(|_, w| {
-            w.afrl5().af5() // SPI1_SCK
-             .afrl7().af5() // SPI1_MOSI
-        });
+        // Bus errors are recorded for `check_bus` rather than aborting the
+        // stream, a bad pixel is redrawn with the next frame
+        let mut push = |bytes: &[u8]| {
+            if len + bytes.len() > BLOCK_LEN {
+                self.record(spi.write(&block[..len]));
+                len = 0;
+            }
+            block[len..len + bytes.len()].copy_from_slice(bytes);
+            len += bytes.len();
+        };
 
-        // Configure SPI1
-        spi1.cr1.modify(|_, w| {
-            w.bidimode().clear_bit()
-             .bidioe().clear_bit()
-             .rxonly().clear_bit()
-             .dff().clear_bit()
-             .lsbfirst().clear_bit()
-             .ssm().set_bit()
-             .ssi().set_bit()
-             .mstr().set_bit()
-             .br().div8()
-             .cpol().clear_bit()
-             .cpha().clear_bit()
-        });
+        match self.color_depth.get() {
+
+            ColorDepth::Bits12 => {
+
+                // RGB 444, each pair of pixels packed as RG BR GB nibbles
+                let mut pixels = pixels.map(|color| {
+                    let (red, green, blue) = color.into_panel(&lut);
+                    ((red & 0xF0) as u16) << 4 | (green & 0xF0) as u16 | (blue >> 4) as u16
+                });
+
+                while let Some(first) = pixels.next() {
+
+                    match pixels.next() {
+                        Some(second) => push(&[
+                            (first >> 4) as u8,
+                            ((first << 4) as u8 & 0xF0) | (second >> 8) as u8,
+                            second as u8
+                        ]),
+                        // Odd pixel count, the trailing nibble is ignored
+                        None => push(&[(first >> 4) as u8, (first << 4) as u8])
+                    }
+                }
+            }
+
+            ColorDepth::Bits16 => {
+
+                for color in pixels {
+                    let (red, green, blue) = color.into_panel(&lut);
+                    push(&Rgb565::from_rgb888(red, green, blue).0.to_be_bytes());
+                }
+            }
 
-        // Enable SPI1
-        spi1.cr1.modify(|_, w| w.spe().set_bit());
+            ColorDepth::Bits18 => {
+
+                // RGB 666 (panel default), sent as the top bits of RGB 888 bytes
+                for color in pixels {
+                    let (red, green, blue) = color.into_panel(&lut);
+                    push(&[red, green, blue]);
+                }
+            }
+        }
 
-        ST7735 { spi: spi1, gpio: gpioa, width, height }
+        if len > 0 {
+            self.record(spi.write(&block[..len]));
+        }
     }
 
-    fn spi_write(&self, byte: u8) {
-        // Wait for TX buffer to be empty
-        while self.spi.sr.read().txe().bit_is_clear() {}
+    // Issue a read command and clock the response into `buf` (at least 2 bytes)
+    fn read_command(&self, command: u8, buf: &mut [u8]) -> Result<(), DisplayError> {
 
-        self.spi.dr.write(|w| w.dr().bits(byte.into()));
+        self.with_cs(|| {
+            self.register_select(ControlMode::Command);
+            self.spi_write(&[command]);
 
-        // Wait for SPI to be busy (TX started)
-        while self.spi.sr.read().bsy().bit_is_set() {}
+            self.spi.borrow_mut().read(buf).map_err(|_| DisplayError::Bus)
+        })
     }
 
-    fn reset(&self, state: PinState) {
-        match state {
-            PinState::Enable => self.gpio.bsrr.write(|w| w.br1().set_bit()),
-            PinState::Disable => self.gpio.bsrr.write(|w| w.bs1().set_bit())
+    // Bus errors are recorded for `check_bus`
+    fn spi_write(&self, bytes: &[u8]) {
+        self.record(self.spi.borrow_mut().write(bytes));
+    }
+
+    // Latch a failed bus operation for `check_bus` and push it to `FAULTS`
+    fn record<E>(&self, result: Result<(), E>) {
+        if result.is_err() {
+            self.bus_fault.set(true);
+            FAULTS.push(Fault::DisplayBus);
+        }
+    }
+
+    // Wait until the last byte has been shifted out
+    //
+    // Writes don't wait for the bus to drain so the shift register stays fed
+    // back to back, anything that changes D/C or CS must flush first
+    fn spi_flush(&self) {
+        self.record(self.spi.borrow_mut().flush());
+    }
+
+    // Busy-wait `cs_dwell` iterations, kept portable so the driver doesn't
+    // depend on a particular core
+    fn dwell(&self) {
+        for _ in 0..self.cs_dwell {
+            core::hint::spin_loop();
         }
     }
 
+    fn reset(&self, state: PinState) {
+        let mut rst = self.rst.borrow_mut();
+        let _ = match state {
+            PinState::Enable => rst.set_low(),
+            PinState::Disable => rst.set_high()
+        };
+    }
+
+    // Run `f` with CS asserted, always leaving D/C on command and CS released
+    //
+    // Every transfer goes through here so no return path can leave the panel
+    // selected (and the bus blocked for other panels sharing SPI1).
+    fn with_cs<R>(&self, f: impl FnOnce() -> R) -> R {
+
+        self.chip_select(PinState::Enable);
+
+        let result = f();
+
+        self.register_select(ControlMode::Command);
+        self.chip_select(PinState::Disable);
+
+        result
+    }
+
+    // Holds CS for about one SPI bit period either side of a transfer so the
+    // first and last bits aren't clipped at fast dividers
     fn chip_select(&self, state: PinState) {
         match state {
-            PinState::Enable => self.gpio.bsrr.write(|w| w.br0().set_bit()),
-            PinState::Disable => self.gpio.bsrr.write(|w| w.bs0().set_bit())
+            PinState::Enable => {
+                let _ = self.cs.borrow_mut().set_low();
+                self.dwell(); // Setup
+            }
+            PinState::Disable => {
+                // Let the final frame drain before releasing the bus
+                self.spi_flush();
+                self.dwell(); // Hold
+                let _ = self.cs.borrow_mut().set_high();
+            }
         }
     }
 
     fn register_select(&self, mode: ControlMode) {
-        match mode {
-            ControlMode::Data => self.gpio.bsrr.write(|w| w.bs4().set_bit()),
-            ControlMode::Command => self.gpio.bsrr.write(|w| w.br4().set_bit())
+        // D/C is sampled with the last bit of each byte
+        self.spi_flush();
+        let mut dc = self.dc.borrow_mut();
+        let _ = match mode {
+            ControlMode::Data => dc.set_high(),
+            ControlMode::Command => dc.set_low()
+        };
+    }
+}
+
+// Relative luminance for each CIE 1976 lightness L* from 0 to 100, scaled to
+// 0-65535: Y = ((L* + 16) / 116)^3, or L* / 903.3 near black
+const fn perceived_brightness_lut() -> [u16; 101] {
+
+    let mut lut = [0; 101];
+    let mut lightness = 0;
+
+    while lightness <= 100 {
+
+        let luminance = match lightness <= 8 {
+            true => lightness * u16::MAX as u64 * 10 / 9033,
+            false => (lightness + 16).pow(3) * u16::MAX as u64 / 116u64.pow(3)
+        };
+
+        lut[lightness as usize] = luminance as u16;
+        lightness += 1;
+    }
+
+    lut
+}
+
+#[cfg(test)]
+mod tests {
+
+    use embedded_hal_mock::eh1::{
+        digital::{Mock as PinMock, State, Transaction as PinTransaction},
+        spi::{Mock as SpiMock, Transaction as SpiTransaction}
+    };
+
+    use super::*;
+
+    // Driver on mocks, `done` checks the bus traffic matched expectations
+    struct Bench {
+        display: ST7735<'static, SpiMock<u8>, PinMock, PinMock, PinMock>,
+        spi: SpiMock<u8>,
+        pins: [PinMock; 3]
+    }
+
+    impl Bench {
+
+        fn new(panel: Panel, spi: &[SpiTransaction<u8>], cs: &[PinTransaction], dc: &[PinTransaction]) -> Self {
+
+            // CS is released as soon as the driver is built
+            let cs = [&[PinTransaction::set(State::High)], cs].concat();
+
+            let spi = SpiMock::new(spi);
+            let pins = [PinMock::new(&cs), PinMock::new(dc), PinMock::new(&[])];
+
+            let display = ST7735::with_bus(spi.clone(), pins[0].clone(), pins[1].clone(), pins[2].clone(), panel, 0).unwrap();
+
+            Bench { display, spi, pins }
         }
+
+        fn done(mut self) {
+            self.spi.done();
+            self.pins.iter_mut().for_each(|pin| pin.done());
+        }
+    }
+
+    // Bus traffic of `send_window` for a window of RAM coordinates
+    fn window(x0: u8, y0: u8, x1: u8, y1: u8) -> Vec<SpiTransaction<u8>> {
+        vec![
+            SpiTransaction::flush(),
+            SpiTransaction::write_vec(vec![0x2A]), // CASET
+            SpiTransaction::flush(),
+            SpiTransaction::write_vec(vec![0, x0, 0, x1]),
+            SpiTransaction::flush(),
+            SpiTransaction::write_vec(vec![0x2B]), // RASET
+            SpiTransaction::flush(),
+            SpiTransaction::write_vec(vec![0, y0, 0, y1]),
+            SpiTransaction::flush(),
+            SpiTransaction::write_vec(vec![0x2C]), // RAMWR
+            SpiTransaction::flush()
+        ]
+    }
+
+    // Bus writes of one `send_pixels` call for a line of RGB 666 pixels led
+    // by `colors`, padded with black to the 160 pixel panel height
+    fn line(colors: &[[u8; 3]]) -> Vec<SpiTransaction<u8>> {
+
+        let mut bytes = colors.concat();
+        bytes.resize(160 * 3, 0);
+
+        bytes.chunks(BLOCK_LEN).map(|block| SpiTransaction::write_vec(block.to_vec())).collect()
+    }
+
+    // D/C toggles of `send_window`: each command followed by its data
+    fn window_dc() -> Vec<PinTransaction> {
+        [State::Low, State::High].repeat(3).into_iter().map(PinTransaction::set).collect()
+    }
+
+    #[test]
+    fn set_window_sends_caset_raset_ramwr_with_panel_offset() {
+
+        // Green-tab glass starts at RAM column 2, row 1
+        let bench = Bench::new(Panel::GREEN_TAB_1_8, &window(2, 1, 129, 160), &[], &window_dc());
+
+        bench.display.set_window(0, 0, 127, 159);
+
+        bench.done();
+    }
+
+    #[test]
+    fn draw_row_sends_window_then_pixels() {
+
+        // Line 3 runs down panel column 3 (column-major), 2 pixels long and
+        // padded down the rest of the column
+        let mut spi = window(3, 0, 3, 159);
+        // RGB 666 in RGB 888 bytes, red then blue
+        spi.extend(line(&[[0xFF, 0x00, 0x00], [0x00, 0x00, 0xFF]]));
+        spi.extend([
+            SpiTransaction::flush(), // D/C back to command
+            SpiTransaction::flush() // CS released
+        ]);
+
+        let cs = [PinTransaction::set(State::Low), PinTransaction::set(State::High)];
+        let dc = [window_dc(), vec![PinTransaction::set(State::Low)]].concat();
+
+        let bench = Bench::new(Panel::RED_TAB_1_8, &spi, &cs, &dc);
+
+        bench.display.draw_row(3, &[Rgb565::RED, Rgb565::BLUE]);
+
+        bench.done();
+    }
+
+    #[test]
+    fn stream_rows_sends_column_lines_into_one_transposed_window() {
+
+        // MADCTL with D/C low then high
+        let madctl = |order: u8| vec![
+            SpiTransaction::flush(),
+            SpiTransaction::write_vec(vec![0x36]),
+            SpiTransaction::flush(),
+            SpiTransaction::write_vec(vec![order])
+        ];
+
+        // Lines 0 and 1 down panel columns 0 and 1, the column address
+        // running down the panel while exchanged
+        let spi = [
+            madctl(0x20),
+            window(0, 0, 159, 1),
+            line(&[[0xFF, 0x00, 0x00]]),
+            line(&[[0x00, 0x00, 0xFF]]),
+            madctl(0x00),
+            vec![SpiTransaction::flush(), SpiTransaction::flush()]
+        ].concat();
+
+        let cs = [PinTransaction::set(State::Low), PinTransaction::set(State::High)];
+        let madctl_dc = [State::Low, State::High].map(PinTransaction::set);
+        let dc = [&madctl_dc[..], &window_dc(), &madctl_dc, &[PinTransaction::set(State::Low)]].concat();
+
+        let bench = Bench::new(Panel::RED_TAB_1_8, &spi, &cs, &dc);
+
+        let mut lines = [Rgb565::RED, Rgb565::BLUE].into_iter();
+        let result = bench.display.stream_rows(0, 2, &mut [Rgb565::BLACK], |line| {
+            line[0] = lines.next().unwrap();
+            Ok::<(), ()>(())
+        });
+
+        assert_eq!(result, Ok(()));
+        bench.done();
     }
 }
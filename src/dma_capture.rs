@@ -0,0 +1,224 @@
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use stm32f4::stm32f401;
+
+use crate::{
+    camera::{Camera, CaptureError, CaptureStats, OV7670},
+    color::Rgb565,
+    framebuffer::FrameBuffer,
+    init::InitError
+};
+
+#[cfg(feature = "display")]
+use crate::display::{Display, ST7735};
+
+/*
+    Hardware-latched capture
+
+    PCLK (PA9) is routed to TIM1_CH2 (AF1) in input capture mode. Each
+    sampling edge raises a TIM1_CH2 DMA request, serviced by DMA2 stream 2
+    channel 6, which copies the data byte straight out of the port's IDR into
+    the line buffer. The CPU only follows HREF to frame each line, so the
+    pixel clock is no longer limited by a polling loop.
+
+    Requirements:
+    * D0-D7 must be `DataBus::Contiguous` at offset 0 or 8 so the data is a
+      single byte of IDR
+    * PCLK must be gated during horizontal blanking (`set_pclk_gating(true)`)
+      since every edge is transferred as a pixel byte
+*/
+
+pub struct DmaCapture<'c, 'a> {
+    camera: &'c OV7670<'a>,
+    gpioa: &'a stm32f401::GPIOA,
+    tim1: stm32f401::TIM1,
+    dma2: stm32f401::DMA2,
+    source: u32
+}
+
+impl<'c, 'a> DmaCapture<'c, 'a> {
+
+    // TIM1_CH2 request mapping on DMA2
+    const STREAM: usize = 2;
+    const CHANNEL: u8 = 6;
+
+    pub fn new(
+        rcc: &stm32f401::RCC,
+        gpioa: &'a stm32f401::GPIOA,
+        camera: &'c OV7670<'a>,
+        tim1: stm32f401::TIM1,
+        dma2: stm32f401::DMA2
+    ) -> Result<Self, InitError> {
+
+        let source = camera.data_byte_address().ok_or(InitError::InvalidConfig)?;
+
+        // Enable DMA2 and TIM1 clocks
+        rcc.ahb1enr.modify(|_, w| w.dma2en().enabled());
+        rcc.apb2enr.modify(|_, w| w.tim1en().enabled());
+
+        // Configure PCLK (TIM1_CH2)
+        gpioa.moder.modify(|_, w| w.moder9().alternate());
+        gpioa.afrh.modify(|_, w| w.afrh9().af1());
+
+        // Capture channel 2 straight from TI2, no filter or prescaler
+        tim1.ccmr1_input().modify(|_, w| {
+            w.cc2s().ti2()
+             .ic2f().bits(0)
+        });
+
+        // Free-running counter, only the capture events are used
+        tim1.arr.write(|w| w.arr().bits(0xFFFF));
+        tim1.cr1.modify(|_, w| w.cen().enabled());
+
+        let capture = DmaCapture { camera, gpioa, tim1, dma2, source };
+
+        capture.stream().cr.write(|w| {
+            w.chsel().bits(DmaCapture::CHANNEL)
+             .dir().peripheral_to_memory()
+             .psize().bits8()
+             .msize().bits8()
+             .pinc().fixed()
+             .minc().incremented()
+             .pl().very_high()
+        });
+
+        Ok(capture)
+    }
+
+    /// Capture one line into `buf` using DMA
+    ///
+    /// Pixels past the end of `buf` are dropped.
+    pub fn capture_line(&self, buf: &mut [Rgb565]) -> Result<(), CaptureError> {
+        self.capture_frame_line(buf, false).map(|_| ())
+    }
+
+    // Capture one line into `buf`, returning true without capturing if
+    // `until_vsync` is set and VSYNC ends the frame before the line starts
+    fn capture_frame_line(&self, buf: &mut [Rgb565], until_vsync: bool) -> Result<bool, CaptureError> {
+
+        let stream = self.stream();
+
+        // Sample on the edge the camera drives data for
+        self.tim1.ccer.modify(|_, w| {
+            w.cc2p().bit(self.camera.pclk_inverted.get())
+             .cc2e().set_bit()
+        });
+
+        // Drop any request latched since the last line
+        self.tim1.dier.modify(|_, w| w.cc2de().clear_bit());
+        self.tim1.sr.modify(|_, w| w.cc2if().clear_bit().cc2of().clear_bit());
+
+        self.dma2.lifcr.write(|w| {
+            w.ctcif2().set_bit()
+             .chtif2().set_bit()
+             .cteif2().set_bit()
+             .cdmeif2().set_bit()
+             .cfeif2().set_bit()
+        });
+
+        let len = (buf.len() * 2).min(0xFFFF) as u16;
+
+        // Arm during blanking so the first pixel byte is caught
+        stream.par.write(|w| unsafe { w.pa().bits(self.source) });
+        stream.m0ar.write(|w| unsafe { w.m0a().bits(buf.as_mut_ptr() as u32) });
+        stream.ndtr.write(|w| w.ndt().bits(len));
+
+        compiler_fence(Ordering::SeqCst);
+
+        stream.cr.modify(|_, w| w.en().enabled());
+        self.tim1.dier.modify(|_, w| w.cc2de().set_bit());
+
+        let line_start = match until_vsync {
+            true => self.camera.frame_ended_before_line(),
+            false => self.camera.wait_href(false).map(|()| false)
+        };
+
+        // Bytes are latched by hardware while HREF is active
+        let result = line_start.and_then(|ended| match ended {
+            true => Ok(true),
+            false => self.camera.wait_href(true).map(|()| false)
+        });
+
+        self.tim1.dier.modify(|_, w| w.cc2de().clear_bit());
+        stream.cr.modify(|_, w| w.en().disabled());
+        while stream.cr.read().en().is_enabled() {}
+
+        compiler_fence(Ordering::SeqCst);
+
+        if result? {
+            return Ok(true);
+        }
+
+        let received = (len - stream.ndtr.read().ndt().bits()) as usize;
+
+        if received == 0 {
+            return Err(CaptureError::PclkTimeout);
+        }
+
+        let swap_rb = self.camera.swap_rb.get();
+
+        // Bytes arrive MSB first
+        for pixel in &mut buf[..received / 2] {
+            *pixel = Rgb565(u16::from_be(pixel.0));
+            if swap_rb {
+                *pixel = pixel.swap_rb();
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Capture the next full frame into `frame`, see `OV7670::capture_frame`
+    pub fn capture_frame(&self, frame: &mut FrameBuffer) -> Result<CaptureStats, CaptureError> {
+
+        self.camera.wait_frame_start()?;
+
+        let (mut stats, start) = CaptureStats::start();
+
+        stats.capture_rows(self.camera.frame_rows(), |y| {
+
+            // Armed too late to catch the first byte of this line
+            let late = self.camera.read_href();
+
+            // VSYNC before the line, the remaining rows were lost
+            match self.capture_frame_line(frame.row_mut(y), true)? {
+                true => Ok(None),
+                false => Ok(Some(late))
+            }
+        })?;
+
+        Ok(stats.finish(start))
+    }
+
+    /// Capture a frame and draw it line by line, like `Camera::draw_frame`
+    #[cfg(feature = "display")]
+    pub fn draw_frame(&self, display: &ST7735) -> Result<(), CaptureError> {
+
+        self.camera.wait_frame_start()?;
+
+        let mut buf = [Rgb565::BLACK; OV7670::FRAME_WIDTH];
+
+        for y in 0..self.camera.frame_rows() {
+            self.capture_line(&mut buf)?;
+            display.draw_row(y as u32, &buf);
+        }
+
+        Ok(())
+    }
+
+    /// Stop the capture engine and return PCLK to a plain GPIO input
+    pub fn release(self) -> (stm32f401::TIM1, stm32f401::DMA2) {
+
+        self.tim1.dier.modify(|_, w| w.cc2de().clear_bit());
+        self.tim1.ccer.modify(|_, w| w.cc2e().clear_bit());
+        self.tim1.cr1.modify(|_, w| w.cen().disabled());
+
+        self.gpioa.moder.modify(|_, w| w.moder9().input());
+
+        (self.tim1, self.dma2)
+    }
+
+    fn stream(&self) -> &stm32f401::dma2::ST {
+        &self.dma2.st[DmaCapture::STREAM]
+    }
+}
@@ -0,0 +1,96 @@
+use crate::{camera::OV7670, framebuffer::FrameBuffer, sccb::I2cError};
+
+/*
+    Software auto exposure
+
+    An alternative to the sensor's AEC/AGC: after each captured frame the
+    mean luminance is compared to a target and exposure (then gain) nudged
+    toward it. Slower to react than the sensor's loop, but it only looks at
+    the frame that was actually captured and never hunts on its own.
+
+    let mut aec = AutoExposure::new(110);
+    loop {
+        camera.capture_frame(&mut frame)?;
+        aec.update(&frame, &camera)?;
+    }
+*/
+
+/// Per-frame exposure and gain control toward a target mean luminance
+pub struct AutoExposure {
+    target: u8,
+    // Current settings, read from the camera on the first update
+    settings: Option<(u16, u8)>
+}
+
+impl AutoExposure {
+
+    // Mean luma this close to the target is left alone
+    const TOLERANCE: u8 = 8;
+
+    // Longest exposure kept within one frame, in row intervals
+    const MAX_EXPOSURE: u16 = 500;
+
+    /// Control toward a mean luminance of `target` (0-255)
+    pub const fn new(target: u8) -> Self {
+        AutoExposure { target, settings: None }
+    }
+
+    /// Adjust the camera for the next frame from the mean luminance of `frame`
+    ///
+    /// Takes over from the sensor's AEC and AGC on the first call. Exposure
+    /// is preferred over gain, which adds noise: gain only rises once
+    /// exposure is at its limit, and drops before exposure does. Each step
+    /// goes half way toward the proportional estimate, between 0.75x and
+    /// 1.5x, so the loop settles without overshooting. Returns the
+    /// frame's mean luminance.
+    pub fn update(&mut self, frame: &FrameBuffer, camera: &OV7670) -> Result<u8, I2cError> {
+
+        let pixels = frame.pixels();
+        let sum: u32 = pixels.iter().map(|pixel| pixel.luma() as u32).sum();
+        let mean = (sum / pixels.len().max(1) as u32) as u8;
+
+        if mean.abs_diff(self.target) <= AutoExposure::TOLERANCE {
+            return Ok(mean);
+        }
+
+        let (exposure, gain) = match self.settings {
+            Some(settings) => settings,
+            None => (camera.read_exposure()?, camera.read_gain()?.min(u8::MAX as u16) as u8)
+        };
+
+        // Correction in 1/256ths, damped to half the proportional estimate
+        let scale = (self.target as u32 * 256 / (mean as u32).max(1)).clamp(128, 512);
+        let scale = (scale + 256) / 2;
+
+        let adjust = |value: u32, max: u32| {
+            let scaled = value * scale / 256;
+            // Always move at least one step so small values don't get stuck
+            let scaled = match scale > 256 {
+                true => scaled.max(value + 1),
+                false => scaled.min(value.saturating_sub(1))
+            };
+            scaled.min(max)
+        };
+
+        let (new_exposure, new_gain) = match (scale > 256, exposure < AutoExposure::MAX_EXPOSURE, gain > 0) {
+            // Brighter: lengthen exposure, then add gain
+            (true, true, _) => (adjust(exposure as u32, AutoExposure::MAX_EXPOSURE as u32) as u16, gain),
+            (true, false, _) => (exposure, adjust(gain as u32, u8::MAX as u32) as u8),
+            // Darker: remove gain, then shorten exposure
+            (false, _, true) => (exposure, adjust(gain as u32, u8::MAX as u32) as u8),
+            (false, _, false) => (adjust(exposure as u32, u16::MAX as u32).max(1) as u16, gain)
+        };
+
+        if self.settings.is_none() || new_exposure != exposure {
+            camera.set_exposure(new_exposure)?;
+        }
+
+        if self.settings.is_none() || new_gain != gain {
+            camera.set_gain(new_gain)?;
+        }
+
+        self.settings = Some((new_exposure, new_gain));
+
+        Ok(mean)
+    }
+}
@@ -0,0 +1,128 @@
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{self, Mutex};
+
+#[cfg(feature = "camera")]
+use crate::sccb::I2cError;
+
+/*
+    Fault log
+
+    Drivers don't hold the debug USART, so faults they recover from on
+    their own (a dropped SPI byte, an SCCB transfer that needed a bus
+    recovery) would otherwise go unseen. They're pushed into the `FAULTS`
+    ring instead, and whoever owns the USART drains it between frames:
+
+    FAULTS.drain(|fault, count| warn!(usart_debugger, "{:?} x{}", fault, count));
+
+    A fault repeating back to back is counted rather than stored again, so
+    a failing bus doesn't flush everything else out. Pushing is safe from
+    interrupt handlers.
+*/
+
+/// A fault seen by a driver
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// A display SPI transfer failed (overrun or mode fault), see
+    /// `ST7735::check_bus`
+    #[cfg(feature = "display")]
+    DisplayBus,
+    /// A camera SCCB transfer to register `addr` failed, after a bus
+    /// recovery for timeouts and a stuck bus
+    #[cfg(feature = "camera")]
+    CameraSccb { addr: u8, error: I2cError }
+}
+
+/// Faults pushed by the drivers in this crate
+pub static FAULTS: FaultLog = FaultLog::new();
+
+/// Fixed-size ring of faults, oldest dropped first when full
+pub struct FaultLog(Mutex<RefCell<Ring>>);
+
+struct Ring {
+    entries: [Option<(Fault, u32)>; FaultLog::CAPACITY],
+    // Index of the oldest entry and number of entries held
+    head: usize,
+    len: usize,
+    dropped: u32
+}
+
+impl FaultLog {
+
+    const CAPACITY: usize = 8;
+
+    pub const fn new() -> Self {
+        FaultLog(Mutex::new(RefCell::new(Ring {
+            entries: [None; FaultLog::CAPACITY],
+            head: 0,
+            len: 0,
+            dropped: 0
+        })))
+    }
+
+    /// Record `fault`, or count it again if it's the newest entry
+    pub fn push(&self, fault: Fault) {
+        interrupt::free(|cs| {
+            let mut ring = self.0.borrow(cs).borrow_mut();
+
+            if ring.len > 0 {
+                let newest = (ring.head + ring.len - 1) % FaultLog::CAPACITY;
+                if let Some((last, count)) = &mut ring.entries[newest] {
+                    if *last == fault {
+                        *count = count.saturating_add(1);
+                        return;
+                    }
+                }
+            }
+
+            // Full, make room by dropping the oldest
+            if ring.len == FaultLog::CAPACITY {
+                ring.head = (ring.head + 1) % FaultLog::CAPACITY;
+                ring.len -= 1;
+                ring.dropped = ring.dropped.saturating_add(1);
+            }
+
+            let tail = (ring.head + ring.len) % FaultLog::CAPACITY;
+            ring.entries[tail] = Some((fault, 1));
+            ring.len += 1;
+        });
+    }
+
+    /// Remove the oldest fault and how many times in a row it happened
+    pub fn pop(&self) -> Option<(Fault, u32)> {
+        interrupt::free(|cs| {
+            let mut ring = self.0.borrow(cs).borrow_mut();
+
+            if ring.len == 0 {
+                return None;
+            }
+
+            let head = ring.head;
+            ring.head = (head + 1) % FaultLog::CAPACITY;
+            ring.len -= 1;
+
+            ring.entries[head].take()
+        })
+    }
+
+    /// Pass every fault to `f`, oldest first, emptying the log
+    ///
+    /// Each fault is taken out in its own critical section, so `f` runs with
+    /// interrupts enabled and can take as long as it needs (e.g. to log).
+    pub fn drain(&self, mut f: impl FnMut(Fault, u32)) {
+        while let Some((fault, count)) = self.pop() {
+            f(fault, count);
+        }
+    }
+
+    /// Number of faults lost to a full log, cleared on read
+    pub fn take_dropped(&self) -> u32 {
+        interrupt::free(|cs| core::mem::take(&mut self.0.borrow(cs).borrow_mut().dropped))
+    }
+}
+
+impl Default for FaultLog {
+    fn default() -> Self {
+        FaultLog::new()
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{camera::OV7670, color::Rgb565};
+
+#[cfg(feature = "display")]
+use crate::display::ST7735;
+
+/*
+    Full camera frame held in RAM
+
+    160x120 RGB565 is 38400 bytes, so keep it out of the stack:
+
+    static mut FRAME: FrameBuffer = FrameBuffer::new();
+*/
+
+pub struct FrameBuffer {
+    pixels: [Rgb565; FrameBuffer::WIDTH * FrameBuffer::HEIGHT]
+}
+
+impl FrameBuffer {
+
+    pub const WIDTH: usize = OV7670::FRAME_WIDTH;
+    pub const HEIGHT: usize = OV7670::FRAME_HEIGHT;
+
+    pub const fn new() -> Self {
+        FrameBuffer { pixels: [Rgb565::BLACK; FrameBuffer::WIDTH * FrameBuffer::HEIGHT] }
+    }
+
+    /// Pixel at (x, y), or `None` outside the frame
+    pub fn get(&self, x: usize, y: usize) -> Option<Rgb565> {
+        if x >= FrameBuffer::WIDTH || y >= FrameBuffer::HEIGHT {
+            return None;
+        }
+        Some(self.pixels[y * FrameBuffer::WIDTH + x])
+    }
+
+    /// Set the pixel at (x, y), ignoring points outside the frame
+    pub fn set(&mut self, x: usize, y: usize, color: Rgb565) {
+        if x < FrameBuffer::WIDTH && y < FrameBuffer::HEIGHT {
+            self.pixels[y * FrameBuffer::WIDTH + x] = color;
+        }
+    }
+
+    /// Row `y` of the frame
+    ///
+    /// Panics if `y` is outside the frame.
+    pub fn row(&self, y: usize) -> &[Rgb565] {
+        &self.pixels[y * FrameBuffer::WIDTH..(y + 1) * FrameBuffer::WIDTH]
+    }
+
+    /// Mutable row `y` of the frame
+    ///
+    /// Panics if `y` is outside the frame.
+    pub fn row_mut(&mut self, y: usize) -> &mut [Rgb565] {
+        &mut self.pixels[y * FrameBuffer::WIDTH..(y + 1) * FrameBuffer::WIDTH]
+    }
+
+    /// The whole frame in row-major order
+    pub fn pixels(&self) -> &[Rgb565] {
+        &self.pixels
+    }
+
+    pub fn fill(&mut self, color: Rgb565) {
+        self.pixels.fill(color);
+    }
+
+    /// Draw the frame in one window, in the same orientation as `draw_frame`
+    #[cfg(feature = "display")]
+    pub fn blit_to(&self, display: &ST7735) {
+        display.draw_rows(0, &self.pixels, FrameBuffer::HEIGHT as u32);
+    }
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        FrameBuffer::new()
+    }
+}
@@ -0,0 +1,360 @@
+use core::convert::Infallible;
+
+use cortex_m::asm;
+use embedded_hal::{digital::{self, OutputPin}, pwm::{self, SetDutyCycle}, spi::{self, SpiBus}};
+use stm32f4::stm32f401;
+
+use crate::{constants::{Config, CLK_HZ}, init::InitError, timeout::{retry_with_backoff, wait_while, INIT_ATTEMPTS}};
+
+/*
+    embedded-hal implementations on the F401 PAC
+
+    The display driver is written against embedded-hal traits. These are the
+    register-level implementations used on this board; any other SpiBus and
+    OutputPin implementations (a HAL crate, a mock) can be used instead.
+
+    SPI1 is wired 3-wire: SDA (PA7, SPI1_MOSI) is turned around with
+    BIDIMODE for reads, so no MISO line is needed. That makes it half
+    duplex: `read` and `write` work, full-duplex `transfer`s fail with
+    `SpiError::HalfDuplex`.
+
+    TIM2 channel 1 drives PWM on PA15 (TIM2_CH1, AF1), for a display
+    backlight wired to the MCU instead of tied on.
+
+    Each type borrows its PAC peripheral, and `&SPI1` and friends aren't
+    `Send` because the PAC can't know there's a single core. They are marked
+    `Send` by hand so a driver built on them can move into a `Shared` handle
+    for interrupt handlers.
+*/
+
+/// Failure reported by `Spi1`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpiError {
+    /// A transfer didn't complete in time
+    Timeout,
+    /// A mode fault disabled SPI1 and it couldn't be re-enabled
+    ModeFault,
+    /// A full-duplex transfer was asked of the 3-wire bus, which can only
+    /// write then read
+    HalfDuplex
+}
+
+impl spi::Error for SpiError {
+    fn kind(&self) -> spi::ErrorKind {
+        spi::ErrorKind::Other
+    }
+}
+
+/// SPI1 master, mode 0, 8-bit frames, on PA5 (SCK) and PA7 (SDA)
+pub struct Spi1<'a> {
+    spi: &'a stm32f401::SPI1,
+    divider: u32
+}
+
+// Registers are only accessed from one core, see the module header
+unsafe impl Send for Spi1<'_> {}
+
+impl<'a> Spi1<'a> {
+
+    // Budget for SPI1 to come up in `new`
+    const INIT_TIMEOUT: u32 = CLK_HZ / 100; // ~10ms
+
+    // Budget for a single byte transfer, far longer than a byte at div256
+    // A stalled peripheral fails a transfer instead of hanging the caller
+    const TIMEOUT: u32 = CLK_HZ / 1000; // ~1ms
+
+    pub fn new(
+        rcc: &stm32f401::RCC,
+        gpioa: &stm32f401::GPIOA,
+        spi1: &'a stm32f401::SPI1,
+        config: &Config
+    ) -> Result<Self, InitError> {
+
+        // Enable GPIOA and SPI1 clocks
+        rcc.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+        rcc.apb2enr.modify(|_, w| w.spi1en().enabled());
+
+        // Configure SPI pins
+        gpioa.moder.modify(|_, w| {
+            w.moder5().alternate() // CLK
+             .moder7().alternate() // SDA
+        });
+
+        // Set SPI pin alternate functions
+        gpioa.afrl.modify(|_, w| {
+            w.afrl5().af5() // SPI1_SCK
+             .afrl7().af5() // SPI1_MOSI
+        });
+
+        // SPI1 may already be running for another panel
+        spi1.cr1.modify(|_, w| w.spe().clear_bit());
+
+        // Configure SPI1
+        spi1.cr1.modify(|_, w| {
+            w.bidimode().clear_bit()
+             .bidioe().clear_bit()
+             .rxonly().clear_bit()
+             .dff().clear_bit()
+             .lsbfirst().clear_bit()
+             .ssm().set_bit()
+             .ssi().set_bit()
+             .mstr().set_bit()
+             .br().bits(config.spi_br())
+             .cpol().clear_bit()
+             .cpha().clear_bit()
+        });
+
+        // Enable SPI1
+        let enabled = retry_with_backoff(Spi1::INIT_TIMEOUT, INIT_ATTEMPTS, |budget| {
+            spi1.cr1.modify(|_, w| w.spe().set_bit());
+            wait_while(budget, || spi1.cr1.read().spe().bit_is_clear())
+        });
+
+        if !enabled {
+            return Err(InitError::EnableTimeout);
+        }
+
+        Ok(Spi1 { spi: spi1, divider: config.spi_divider })
+    }
+
+    /// SPI clock divider, i.e. the core cycles per bit
+    pub fn divider(&self) -> u32 {
+        self.divider
+    }
+
+    /// Check that the SPI peripheral has no pending mode-fault or overrun errors
+    pub fn status_ok(&self) -> bool {
+        let sr = self.spi.sr.read();
+        sr.modf().bit_is_clear() && sr.ovr().bit_is_clear()
+    }
+
+    // Clear any overrun or mode fault left by a glitch
+    //
+    // OVR is harmless while only transmitting, but a mode fault clears MSTR
+    // and SPE, silently stalling every following write until it's undone.
+    fn clear_errors(&mut self) -> Result<(), SpiError> {
+
+        let sr = self.spi.sr.read();
+
+        // Cleared by reading DR then SR
+        if sr.ovr().bit_is_set() {
+            self.spi.dr.read().bits();
+            self.spi.sr.read().bits();
+        }
+
+        // Cleared by the SR read followed by a CR1 write
+        if sr.modf().bit_is_set() {
+            self.spi.cr1.modify(|_, w| w.mstr().set_bit().spe().set_bit());
+
+            if self.spi.sr.read().modf().bit_is_set() || self.spi.cr1.read().spe().bit_is_clear() {
+                return Err(SpiError::ModeFault);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Switch SDA between transmitting and receiving
+    // The clock runs continuously as soon as SPI is enabled in receive mode
+    fn set_receive(&self, on: bool) {
+        self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+        self.spi.cr1.modify(|_, w| {
+            w.bidimode().bit(on)
+             .bidioe().clear_bit()
+        });
+        self.spi.cr1.modify(|_, w| w.spe().set_bit());
+    }
+}
+
+impl spi::ErrorType for Spi1<'_> {
+    type Error = SpiError;
+}
+
+impl SpiBus<u8> for Spi1<'_> {
+
+    // Receive bytes on SDA using bidirectional receive-only mode
+    fn read(&mut self, words: &mut [u8]) -> Result<(), SpiError> {
+
+        // Let the last byte finish before turning the line around
+        self.flush()?;
+
+        // Transmitting filled the RX buffer, clear it and the overrun flag
+        self.spi.dr.read().bits();
+        self.spi.sr.read().bits();
+
+        self.set_receive(true);
+
+        let mut result = Ok(());
+
+        for i in 0..words.len() {
+
+            if !wait_while(Spi1::TIMEOUT, || self.spi.sr.read().rxne().bit_is_clear()) {
+                result = Err(SpiError::Timeout);
+                break;
+            }
+
+            words[i] = self.spi.dr.read().dr().bits() as u8;
+
+            // Stop the clock so it halts after the last byte:
+            // after the second to last byte wait one SPI clock then disable SPI
+            if i + 2 == words.len() {
+                asm::delay(self.divider);
+                self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+            }
+        }
+
+        // Back to transmitting on SDA
+        self.set_receive(false);
+
+        result
+    }
+
+    // Only waits for TXE so the shift register stays fed back to back
+    fn write(&mut self, words: &[u8]) -> Result<(), SpiError> {
+
+        self.clear_errors()?;
+
+        for &byte in words {
+            // Wait for TX buffer to be empty
+            if !wait_while(Spi1::TIMEOUT, || self.spi.sr.read().txe().bit_is_clear()) {
+                return Err(SpiError::Timeout);
+            }
+
+            self.spi.dr.write(|w| w.dr().bits(byte.into()));
+        }
+
+        Ok(())
+    }
+
+    // SDA is shared, so nothing can be clocked in while writing. Use `write`
+    // then `read` instead
+    fn transfer(&mut self, _read: &mut [u8], _write: &[u8]) -> Result<(), SpiError> {
+        Err(SpiError::HalfDuplex)
+    }
+
+    fn transfer_in_place(&mut self, _words: &mut [u8]) -> Result<(), SpiError> {
+        Err(SpiError::HalfDuplex)
+    }
+
+    // Wait until the last loaded frame has been shifted out
+    // BSY only rises a few SPI clocks after DR is loaded, so wait for TXE first
+    fn flush(&mut self) -> Result<(), SpiError> {
+        if !wait_while(Spi1::TIMEOUT, || self.spi.sr.read().txe().bit_is_clear())
+            || !wait_while(Spi1::TIMEOUT, || self.spi.sr.read().bsy().bit_is_set()) {
+            return Err(SpiError::Timeout);
+        }
+        Ok(())
+    }
+}
+
+/// A GPIOA pin driven as a push-pull output
+pub struct GpioaPin<'a> {
+    gpio: &'a stm32f401::GPIOA,
+    pin: u8
+}
+
+// Registers are only accessed from one core, see the module header
+unsafe impl Send for GpioaPin<'_> {}
+
+impl<'a> GpioaPin<'a> {
+
+    pub fn new(rcc: &stm32f401::RCC, gpioa: &'a stm32f401::GPIOA, pin: u8) -> Result<Self, InitError> {
+
+        if pin >= 16 {
+            return Err(InitError::InvalidConfig);
+        }
+
+        // Enable GPIOA clock
+        rcc.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+
+        let shift = 2 * pin as u32;
+        gpioa.moder.modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b01 << shift)) });
+
+        Ok(GpioaPin { gpio: gpioa, pin })
+    }
+}
+
+impl digital::ErrorType for GpioaPin<'_> {
+    type Error = Infallible;
+}
+
+impl OutputPin for GpioaPin<'_> {
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.gpio.bsrr.write(|w| unsafe { w.bits(1 << (self.pin + 16)) });
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.gpio.bsrr.write(|w| unsafe { w.bits(1 << self.pin) });
+        Ok(())
+    }
+}
+
+/// TIM2 channel 1 PWM on PA15, 20kHz with 800 duty steps
+///
+/// The period is computed from `CLK_HZ`, so after switching the system
+/// clock (e.g. to the PLL) the PWM runs at the wrong frequency until it's
+/// rebuilt for the new clock.
+pub struct Tim2Pwm<'a> {
+    tim: &'a stm32f401::TIM2
+}
+
+// Registers are only accessed from one core, see the module header
+unsafe impl Send for Tim2Pwm<'_> {}
+
+impl<'a> Tim2Pwm<'a> {
+
+    // Above the audible range so a backlight doesn't whine
+    const PWM_HZ: u32 = 20_000;
+
+    // Counter period, one duty step per core clock
+    const PERIOD: u32 = CLK_HZ / Tim2Pwm::PWM_HZ;
+
+    /// Set up PA15 and TIM2, starting at 0% duty
+    pub fn new(rcc: &stm32f401::RCC, gpioa: &stm32f401::GPIOA, tim2: &'a stm32f401::TIM2) -> Self {
+
+        // Enable GPIOA and TIM2 clocks
+        rcc.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+        rcc.apb1enr.modify(|_, w| w.tim2en().enabled());
+
+        // PA15 as TIM2_CH1, taking it from JTDI (SWD doesn't use it)
+        gpioa.moder.modify(|_, w| w.moder15().alternate());
+        gpioa.afrh.modify(|_, w| w.afrh15().af1());
+
+        tim2.cr1.modify(|_, w| w.cen().clear_bit());
+
+        tim2.psc.write(|w| w.psc().bits(0));
+        tim2.arr.write(|w| w.bits(Tim2Pwm::PERIOD - 1));
+        tim2.ccr[0].write(|w| w.bits(0));
+
+        // PWM mode 1 (high while CNT < CCR1) with CCR1 preloaded, so duty
+        // changes take effect at the next period instead of glitching
+        tim2.ccmr1_output().modify(|_, w| w.cc1s().output().oc1m().pwm_mode1().oc1pe().enabled());
+        tim2.ccer.modify(|_, w| w.cc1p().clear_bit().cc1e().set_bit());
+
+        // Load the preloaded registers, then start counting
+        tim2.cr1.modify(|_, w| w.arpe().enabled());
+        tim2.egr.write(|w| w.ug().set_bit());
+        tim2.cr1.modify(|_, w| w.cen().set_bit());
+
+        Tim2Pwm { tim: tim2 }
+    }
+}
+
+impl pwm::ErrorType for Tim2Pwm<'_> {
+    type Error = Infallible;
+}
+
+impl SetDutyCycle for Tim2Pwm<'_> {
+
+    fn max_duty_cycle(&self) -> u16 {
+        Tim2Pwm::PERIOD as u16
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Infallible> {
+        let duty = (duty as u32).min(Tim2Pwm::PERIOD);
+        self.tim.ccr[0].write(|w| w.bits(duty));
+        Ok(())
+    }
+}
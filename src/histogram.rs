@@ -0,0 +1,41 @@
+use crate::color::Rgb565;
+
+/// Bucket pixel luminance into 16 equal bins
+///
+/// Bin n counts pixels with luma in `16n..16n + 16`. Pass
+/// `FrameBuffer::pixels` for a whole frame or a single row.
+pub fn luma_histogram(pixels: &[Rgb565]) -> [u32; 16] {
+
+    let mut bins = [0; 16];
+
+    for pixel in pixels {
+        bins[(pixel.luma() >> 4) as usize] += 1;
+    }
+
+    bins
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn black_white_and_grey_land_in_their_bins() {
+
+        let grey = Rgb565::from_rgb888(0x80, 0x80, 0x80);
+        let pixels = [Rgb565::BLACK, Rgb565::BLACK, grey, Rgb565::WHITE, Rgb565::WHITE, Rgb565::WHITE];
+
+        let bins = luma_histogram(&pixels);
+
+        assert_eq!(bins[0], 2);
+        assert_eq!(bins[8], 1);
+        assert_eq!(bins[15], 3);
+        assert_eq!(bins.iter().sum::<u32>(), pixels.len() as u32);
+    }
+
+    #[test]
+    fn no_pixels_leave_every_bin_empty() {
+        assert_eq!(luma_histogram(&[]), [0; 16]);
+    }
+}
@@ -0,0 +1,10 @@
+/// Reason a driver constructor failed to bring up its peripheral
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InitError {
+    /// A clock source never reported ready
+    ClockTimeout,
+    /// A peripheral never confirmed it was enabled
+    EnableTimeout,
+    /// The requested configuration can't be applied (e.g. pin out of range)
+    InvalidConfig
+}
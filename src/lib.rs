@@ -0,0 +1,41 @@
+#![cfg_attr(not(test), no_std)]
+
+pub mod constants;
+pub mod delay;
+pub mod color;
+pub mod crc;
+pub mod init;
+#[cfg(feature = "usart")]
+pub mod usart_debugger;
+pub mod logger;
+#[cfg(any(feature = "camera", feature = "display"))]
+pub mod fault;
+#[cfg(feature = "display")]
+pub mod display;
+#[cfg(feature = "camera")]
+pub mod camera;
+#[cfg(feature = "camera")]
+pub mod sccb;
+#[cfg(all(feature = "camera", feature = "display"))]
+pub mod selftest;
+#[cfg(feature = "display")]
+pub mod console;
+#[cfg(feature = "camera")]
+pub mod framebuffer;
+#[cfg(feature = "camera")]
+pub mod motion;
+#[cfg(feature = "camera")]
+pub mod dma_capture;
+pub mod histogram;
+#[cfg(feature = "camera")]
+pub mod exposure;
+pub mod shared;
+#[cfg(feature = "display")]
+pub mod hal;
+#[cfg(all(feature = "camera", feature = "display"))]
+pub mod cli;
+#[cfg(all(feature = "camera", feature = "display", feature = "usart"))]
+pub mod viewfinder;
+
+#[cfg(any(feature = "camera", feature = "display", feature = "usart"))]
+mod timeout;
@@ -0,0 +1,76 @@
+use core::fmt::{self, Write};
+
+/*
+    Leveled logging over any `fmt::Write` sink (e.g. `UsartDebugger`)
+
+    The maximum level is picked at compile time with the `log-warn`,
+    `log-error` or `log-off` features. Records above it compile out.
+
+    info!(usart_debugger, "Calibrating {}", "display");
+*/
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info
+}
+
+impl Level {
+    fn label(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO"
+        }
+    }
+}
+
+/// Most verbose level that is emitted; `None` disables logging entirely
+pub const MAX_LEVEL: Option<Level> = if cfg!(feature = "log-off") {
+    None
+} else if cfg!(feature = "log-error") {
+    Some(Level::Error)
+} else if cfg!(feature = "log-warn") {
+    Some(Level::Warn)
+} else {
+    Some(Level::Info)
+};
+
+/// Whether records at `level` are emitted
+pub const fn enabled(level: Level) -> bool {
+    match MAX_LEVEL {
+        Some(max) => level as u8 <= max as u8,
+        None => false
+    }
+}
+
+/// Write a single record prefixed with its level
+pub fn write_record(out: &mut impl Write, level: Level, args: fmt::Arguments) {
+    // Logging is best effort, a failing sink must not take the caller down
+    let _ = write!(out, "[{}] {}\r\n", level.label(), args);
+}
+
+#[macro_export]
+macro_rules! log {
+    ($out:expr, $level:expr, $($arg:tt)*) => {
+        if $crate::logger::enabled($level) {
+            $crate::logger::write_record(&mut $out, $level, format_args!($($arg)*));
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($out:expr, $($arg:tt)*) => { $crate::log!($out, $crate::logger::Level::Info, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($out:expr, $($arg:tt)*) => { $crate::log!($out, $crate::logger::Level::Warn, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($out:expr, $($arg:tt)*) => { $crate::log!($out, $crate::logger::Level::Error, $($arg)*) };
+}
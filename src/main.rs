@@ -1,49 +1,165 @@
 #![no_std]
 #![no_main]
 
-mod constants;
-mod usart_debugger;
-mod display;
-mod camera;
-
-use core::fmt::Write;
 use cortex_m_rt::entry;
 use panic_halt as _;
 use stm32f4::stm32f401;
 
-use usart_debugger::UsartDebugger;
-use display::{Display, ST7735};
-use camera::{Camera, OV7670};
+use stm32_rs_cam_display::constants::Config;
+use stm32_rs_cam_display::delay::CycleDelay;
+
+/*
+    The application depends on the drivers built in:
+
+    camera + display + usart  viewfinder with the serial command line
+    display                   test pattern, for checking the panel
+    camera + usart            frames streamed to a host with `stream_frame`
+
+    Any other combination has nothing to show and just idles.
+*/
 
 #[entry]
 fn main() -> ! {
     let dp = stm32f401::Peripherals::take().unwrap();
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+
+    // Cycle counter for capture timing and benchmarks
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    app(dp, &Config::DEFAULT, &mut CycleDelay)
+}
+
+#[cfg(all(feature = "camera", feature = "display", feature = "usart"))]
+fn app(dp: stm32f401::Peripherals, config: &Config, delay: &mut CycleDelay) -> ! {
+
+    use stm32_rs_cam_display::usart_debugger::UsartDebugger;
+    use stm32_rs_cam_display::display::{ControlPins, Panel, ST7735};
+    use stm32_rs_cam_display::hal::Tim2Pwm;
+    use stm32_rs_cam_display::camera::{DataBus, OV7670};
+    use stm32_rs_cam_display::selftest::{benchmark_capture, selftest};
+    use stm32_rs_cam_display::cli::{self, LineBuffer};
+    use stm32_rs_cam_display::viewfinder::Viewfinder;
+    use stm32_rs_cam_display::{error, info};
 
     let rcc = &dp.RCC;
     let gpioa = &dp.GPIOA;
-    let gpiob = &dp.GPIOB;
-    let gpioc = &dp.GPIOC;
 
-    let mut usart_debugger = UsartDebugger::new(rcc, gpioa, dp.USART2);
+    let mut usart_debugger = UsartDebugger::new(rcc, gpioa, dp.USART2, config);
+
+    // PWM for a backlight wired to PA15, harmless when it's tied on
+    let mut backlight = Tim2Pwm::new(rcc, gpioa, &dp.TIM2);
+
+    let display = match ST7735::new(rcc, gpioa, &dp.SPI1, ControlPins::default(), Panel::default(), config) {
+        Ok(display) => display.with_backlight(&mut backlight),
+        Err(error) => {
+            error!(usart_debugger, "Display init failed ({:?})", error);
+            panic!();
+        }
+    };
+
+    let camera = match OV7670::new(rcc, gpioa, &dp.GPIOB, &dp.GPIOC, dp.I2C1, DataBus::default(), config) {
+        Ok(camera) => camera,
+        Err(error) => {
+            error!(usart_debugger, "Camera init failed ({:?})", error);
+            panic!();
+        }
+    };
+
+    // The PWM starts at 0%, which would leave a PA15 backlight dark
+    display.set_brightness(100);
+
+    // Hide the torn frames after calibration and mode changes
+    camera.set_settle_frames(2);
+
+    let mut viewfinder = Viewfinder::new(camera, display, usart_debugger);
+
+    // Failures are logged, carry on so the self-test shows what's wrong
+    let _ = viewfinder.calibrate(delay);
+
+    let (usart_debugger, camera, display) = viewfinder.parts();
+
+    selftest(usart_debugger, camera, display).unwrap();
+    benchmark_capture(usart_debugger, camera).unwrap();
 
-    let display = ST7735::new(rcc, gpioa, dp.SPI1, 128, 160);
+    info!(*usart_debugger, "Entering color loop");
 
-    let camera = OV7670::new(rcc, gpioa, gpiob, gpioc, dp.I2C1);
+    let mut line = LineBuffer::new();
 
+    // Serial commands are handled between frames
+    viewfinder.run(delay, &mut |usart_debugger, camera, display| {
+        while let Some(byte) = usart_debugger.read_byte() {
+            if let Some(command) = line.push(byte) {
+                let _ = cli::handle(command, usart_debugger, camera, display);
+            }
+        }
+    })
+}
+
+#[cfg(all(feature = "display", not(all(feature = "camera", feature = "usart"))))]
+fn app(dp: stm32f401::Peripherals, config: &Config, delay: &mut CycleDelay) -> ! {
+
+    use stm32_rs_cam_display::display::{ControlPins, Display, Panel, ST7735};
+
+    // Nothing to report a failure to, halt
+    let display = ST7735::new(&dp.RCC, &dp.GPIOA, &dp.SPI1, ControlPins::default(), Panel::default(), config).unwrap();
+
+    display.calibrate(delay);
+    display.draw_test_pattern();
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
 
-    write!(usart_debugger, "Calibrating display\r\n").unwrap();
+#[cfg(all(feature = "camera", feature = "usart", not(feature = "display")))]
+fn app(dp: stm32f401::Peripherals, config: &Config, delay: &mut CycleDelay) -> ! {
 
-    display.calibrate();
+    use stm32_rs_cam_display::usart_debugger::UsartDebugger;
+    use stm32_rs_cam_display::camera::{Camera, DataBus, OV7670};
+    use stm32_rs_cam_display::framebuffer::FrameBuffer;
+    use stm32_rs_cam_display::{error, warn};
 
+    static mut FRAME: FrameBuffer = FrameBuffer::new();
 
-    write!(usart_debugger, "Calibrating camera\r\n").unwrap();
+    let rcc = &dp.RCC;
+    let gpioa = &dp.GPIOA;
+
+    // Frames are streamed with DMA so the link runs at full baud
+    let mut usart_debugger = UsartDebugger::new(rcc, gpioa, dp.USART2, config).with_dma(rcc, dp.DMA1);
 
-    camera.calibrate();
+    let camera = match OV7670::new(rcc, gpioa, &dp.GPIOB, &dp.GPIOC, dp.I2C1, DataBus::default(), config) {
+        Ok(camera) => camera,
+        Err(error) => {
+            error!(usart_debugger, "Camera init failed ({:?})", error);
+            panic!();
+        }
+    };
+
+    if let Err(error) = camera.calibrate(delay) {
+        error!(usart_debugger, "Camera calibration failed ({:?})", error);
+    }
 
+    // Only used here, and main never returns
+    let frame = unsafe { &mut *core::ptr::addr_of_mut!(FRAME) };
 
-    write!(usart_debugger, "Entering color loop\r\n").unwrap();
+    loop {
+        if let Err(error) = camera.stream_frame(frame, &mut usart_debugger) {
+            warn!(usart_debugger, "Capture failed ({:?}), recalibrating camera", error);
+            let _ = camera.calibrate(delay);
+        }
+
+        // Faults are left in `FAULTS` rather than logged: text between frames
+        // would land in the binary stream the host is parsing
+    }
+}
 
+#[cfg(not(any(
+    all(feature = "camera", feature = "usart"),
+    feature = "display"
+)))]
+fn app(_dp: stm32f401::Peripherals, _config: &Config, _delay: &mut CycleDelay) -> ! {
     loop {
-        camera.draw_frame(&display);
+        cortex_m::asm::wfi();
     }
 }
@@ -0,0 +1,113 @@
+use crate::framebuffer::FrameBuffer;
+
+/// Region of a frame, in frame pixel coordinates
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32
+}
+
+/// Result of comparing two frames, see `detect_motion`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Motion {
+    /// Summed absolute luminance difference over the whole frame
+    pub difference: u32,
+    /// Number of pixels whose luminance changed by more than the threshold
+    pub changed: u32,
+    /// Bounding box of the changed pixels, `None` if nothing changed
+    pub bounds: Option<Region>,
+    /// Threshold the frames were compared with
+    pub threshold: u8
+}
+
+impl Motion {
+
+    /// Whether the frames differ by more than `threshold` per pixel on
+    /// average
+    ///
+    /// Judged on the summed difference, so a few noisy pixels don't count
+    /// as motion however far they jump.
+    pub fn detected(&self) -> bool {
+        let pixels = (FrameBuffer::WIDTH * FrameBuffer::HEIGHT) as u32;
+        self.difference > self.threshold as u32 * pixels
+    }
+}
+
+/// Compare two frames by luminance
+///
+/// A pixel counts as changed, and is included in `bounds`, when its
+/// luminance differs by more than `threshold`; motion is reported when the
+/// summed difference exceeds `threshold` for every pixel, see
+/// `Motion::detected`. Raise the threshold to ignore sensor noise.
+pub fn detect_motion(prev: &FrameBuffer, cur: &FrameBuffer, threshold: u8) -> Motion {
+
+    let mut difference = 0;
+    let mut changed = 0;
+
+    // Bounding box as inclusive min/max corners
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+
+    for y in 0..FrameBuffer::HEIGHT {
+        for (x, (a, b)) in prev.row(y).iter().zip(cur.row(y)).enumerate() {
+
+            let delta = a.luma().abs_diff(b.luma());
+            difference += delta as u32;
+
+            if delta <= threshold {
+                continue;
+            }
+
+            changed += 1;
+
+            let (x, y) = (x as u32, y as u32);
+            bounds = Some(match bounds {
+                None => (x, y, x, y),
+                Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y))
+            });
+        }
+    }
+
+    Motion {
+        difference,
+        changed,
+        bounds: bounds.map(|(x0, y0, x1, y1)| Region { x: x0, y: y0, w: x1 - x0 + 1, h: y1 - y0 + 1 }),
+        threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::color::Rgb565;
+
+    use super::*;
+
+    #[test]
+    fn single_changed_pixel_is_not_motion() {
+
+        let prev = FrameBuffer::new();
+        let mut cur = FrameBuffer::new();
+        cur.set(10, 20, Rgb565::WHITE);
+
+        let motion = detect_motion(&prev, &cur, 8);
+
+        assert_eq!(motion.changed, 1);
+        assert_eq!(motion.bounds, Some(Region { x: 10, y: 20, w: 1, h: 1 }));
+        assert!(!motion.detected());
+    }
+
+    #[test]
+    fn whole_frame_change_is_motion() {
+
+        let prev = FrameBuffer::new();
+        let mut cur = FrameBuffer::new();
+        cur.fill(Rgb565::WHITE);
+
+        let motion = detect_motion(&prev, &cur, 8);
+
+        assert_eq!(motion.changed, (FrameBuffer::WIDTH * FrameBuffer::HEIGHT) as u32);
+        assert!(motion.detected());
+    }
+}
@@ -0,0 +1,470 @@
+use cortex_m::asm;
+use stm32f4::stm32f401;
+
+use crate::{constants::{Speed, CLK_HZ}, init::InitError, timeout::wait_while};
+
+/*
+    SCCB framing
+
+    The OV7670 register protocol is built on a handful of I2C master
+    operations. Keeping the framing generic over `I2cBus` lets it run
+    against a mock bus off-target.
+
+    Write: START | ADDR+W | REG | DATA | STOP
+    Read:  START | ADDR+W | REG | STOP
+           (bus free)
+           START | ADDR+R | DATA (NACK) | STOP
+
+    A read is two separate transactions, not a repeated START. Strict SCCB
+    parts also need the first STOP to have fully finished, and the bus to
+    sit idle for the bus-free time, before the second START.
+*/
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum I2cError {
+    /// A bus event didn't happen in time, usually a stuck bus
+    Timeout,
+    /// The device didn't acknowledge its address or a data byte
+    Nack,
+    /// The bus stayed busy, so no START could be generated
+    Busy
+}
+
+/// Low-level I2C master operations the SCCB protocol is built on
+pub trait I2cBus {
+
+    /// Generate a START condition once the bus is free
+    fn start(&self) -> Result<(), I2cError>;
+
+    /// Send a 7-bit device address with the R/W bit and wait for the acknowledge
+    fn address(&self, device: u8, read: bool) -> Result<(), I2cError>;
+
+    /// Write a byte and wait for it to be transmitted
+    fn write_byte(&self, byte: u8) -> Result<(), I2cError>;
+
+    /// Receive a single byte, NACKing it and generating a STOP condition
+    fn read_last_byte(&self) -> Result<u8, I2cError>;
+
+    /// Generate a STOP condition
+    fn stop(&self);
+
+    /// Wait for a STOP condition to finish and the bus-free time to pass
+    fn wait_stop(&self) -> Result<(), I2cError>;
+}
+
+/// Write `data` to register `reg` of `device`
+pub fn write(bus: &impl I2cBus, device: u8, reg: u8, data: u8) -> Result<(), I2cError> {
+
+    let result = (|| {
+        bus.start()?;
+        bus.address(device, false)?;
+        bus.write_byte(reg)?;
+        bus.write_byte(data)
+    })();
+
+    // Always release the bus, even after a failure
+    bus.stop();
+
+    result
+}
+
+/// Read register `reg` of `device`
+pub fn read(bus: &impl I2cBus, device: u8, reg: u8) -> Result<u8, I2cError> {
+
+    // Select the register
+    write_address(bus, device, reg)?;
+
+    // Finish the first transaction before starting the second
+    bus.wait_stop()?;
+
+    // Read it back
+    let result = (|| {
+        bus.start()?;
+        bus.address(device, true)
+    })();
+
+    match result {
+        Ok(()) => bus.read_last_byte(),
+        Err(error) => {
+            bus.stop();
+            Err(error)
+        }
+    }
+}
+
+// Two-phase write that only sets the register address
+fn write_address(bus: &impl I2cBus, device: u8, reg: u8) -> Result<(), I2cError> {
+
+    let result = (|| {
+        bus.start()?;
+        bus.address(device, false)?;
+        bus.write_byte(reg)
+    })();
+
+    bus.stop();
+
+    result
+}
+
+// I2C1 register values for a bus speed and input clock
+pub(crate) struct I2cTiming {
+    freq: u8,
+    fast: bool,
+    duty: bool,
+    ccr: u16,
+    trise: u8
+}
+
+impl I2cTiming {
+
+    // Compute the timing for `scl_hz` off an `apb1_hz` input clock, `None` if
+    // the combination isn't achievable
+    //
+    // Standard mode splits each SCL period 1:1 high/low. Fast mode rounds up
+    // so SCL never exceeds `scl_hz`, using whichever DUTY gets closer: clear
+    // is 2:1 low/high (period 3 × CCR), set is 16:9 (period 25 × CCR).
+    pub(crate) const fn new(apb1_hz: u32, speed: Speed, scl_hz: u32) -> Option<Self> {
+
+        let mhz = apb1_hz / 1_000_000;
+
+        // (max SCL, min FREQ, min CCR, max rise time in ns)
+        let (max_scl, min_mhz, min_ccr, rise_ns) = match speed {
+            Speed::Standard => (100_000, 2, 4, 1000),
+            Speed::Fast => (400_000, 4, 1, 300)
+        };
+
+        // FREQ is the input clock in MHz, 2-50 on the F401
+        if mhz < min_mhz || mhz > 50 || scl_hz == 0 || scl_hz > max_scl {
+            return None;
+        }
+
+        let (ccr, duty) = match speed {
+            Speed::Standard => (apb1_hz / (2 * scl_hz), false),
+            Speed::Fast => {
+                let ccr2 = apb1_hz.div_ceil(3 * scl_hz);
+                let ccr16 = apb1_hz.div_ceil(25 * scl_hz);
+                if 25 * ccr16 < 3 * ccr2 { (ccr16, true) } else { (ccr2, false) }
+            }
+        };
+
+        // CCR is 12 bits wide
+        if ccr < min_ccr || ccr > 0xFFF {
+            return None;
+        }
+
+        // TRISE is the rise time in input clocks plus one, in 6 bits
+        let trise = mhz * rise_ns / 1000 + 1;
+        if trise > 0x3F {
+            return None;
+        }
+
+        Some(I2cTiming {
+            freq: mhz as u8,
+            fast: matches!(speed, Speed::Fast),
+            duty,
+            ccr: ccr as u16,
+            trise: trise as u8
+        })
+    }
+}
+
+/// Program I2C1's FREQ, CCR and TRISE for `scl_hz` off an `apb1_hz` input clock
+///
+/// Leaves I2C1 disabled, as CCR may only change while it is. Call again
+/// whenever APB1 changes, e.g. after switching to the PLL. Fails with
+/// `InvalidConfig` if APB1 is outside the 2-50MHz FREQ range (4MHz for fast
+/// mode) or `scl_hz` can't be reached with CCR and TRISE in their fields.
+pub fn configure_i2c_timing(
+    i2c: &stm32f401::I2C1,
+    apb1_hz: u32,
+    speed: Speed,
+    scl_hz: u32
+) -> Result<(), InitError> {
+
+    let Some(timing) = I2cTiming::new(apb1_hz, speed, scl_hz) else {
+        return Err(InitError::InvalidConfig);
+    };
+
+    i2c.cr1.modify(|_, w| w.pe().disabled());
+
+    // Specify I2C1 input clock frequency for timing
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(timing.freq) });
+
+    // Configure I2C1_SCL in standard or fast mode
+    i2c.ccr.modify(|_, w| unsafe {
+        w.f_s().bit(timing.fast);
+        w.duty().bit(timing.duty);
+        w.ccr().bits(timing.ccr)
+    });
+
+    // Configure I2C rise time
+    i2c.trise.modify(|_, w| w.trise().bits(timing.trise));
+
+    Ok(())
+}
+
+// Budget for any single bus event, ~10 byte times at 100KHz
+const TIMEOUT: u32 = CLK_HZ / 1000; // ~1ms
+
+// Idle time between a STOP and the next START
+const BUS_FREE: u32 = CLK_HZ / 1_000_000 * 13 / 5; // ~2.6us
+
+// Longest a device may stretch the clock, the SMBus limit
+const STRETCH_TIMEOUT: u32 = CLK_HZ / 40; // ~25ms
+
+/// I2C1 with SCL on PB8, as wired to the OV7670
+///
+/// GPIOB is only read, to tell a device stretching the clock from a hung bus.
+pub struct Sccb1<'a> {
+    i2c: &'a stm32f401::I2C1,
+    gpiob: &'a stm32f401::GPIOB
+}
+
+impl<'a> Sccb1<'a> {
+    pub fn new(i2c: &'a stm32f401::I2C1, gpiob: &'a stm32f401::GPIOB) -> Self {
+        Sccb1 { i2c, gpiob }
+    }
+
+    // Wait for a bus event, tolerating clock stretching. IDR follows SCL
+    // (PB8) in alternate function mode
+    fn wait_event(&self, pending: impl FnMut() -> bool) -> Result<(), I2cError> {
+        wait_event(pending, || self.gpiob.idr.read().idr8().bit_is_clear())
+    }
+}
+
+impl I2cBus for Sccb1<'_> {
+
+    fn start(&self) -> Result<(), I2cError> {
+
+        let i2c = self.i2c;
+
+        // A START while another transfer (or a STOP) is still on the bus can hang
+        if !wait_while(TIMEOUT, || i2c.sr2.read().busy().bit_is_set()) {
+            return Err(I2cError::Busy);
+        }
+
+        i2c.cr1.modify(|_, w| w.start().set_bit());
+        self.wait_event(|| i2c.sr1.read().sb().bit_is_clear())
+    }
+
+    fn address(&self, device: u8, read: bool) -> Result<(), I2cError> {
+
+        let i2c = self.i2c;
+
+        i2c.dr.write(|w| w.dr().bits((device << 1) | read as u8));
+
+        self.wait_event(|| {
+            let sr1 = i2c.sr1.read();
+            sr1.addr().bit_is_clear() && sr1.af().bit_is_clear()
+        })?;
+
+        check_ack(i2c)?;
+
+        i2c.sr2.read().bits(); // Read to clear addr sent flag
+
+        Ok(())
+    }
+
+    fn write_byte(&self, byte: u8) -> Result<(), I2cError> {
+
+        let i2c = self.i2c;
+
+        i2c.dr.write(|w| w.dr().bits(byte));
+
+        self.wait_event(|| {
+            let sr1 = i2c.sr1.read();
+            sr1.btf().bit_is_clear() && sr1.af().bit_is_clear()
+        })?;
+
+        check_ack(i2c)
+    }
+
+    fn read_last_byte(&self) -> Result<u8, I2cError> {
+
+        let i2c = self.i2c;
+
+        // NACK next byte, send stop signal
+        i2c.cr1.modify(|_, w| {
+            w.ack().clear_bit()
+             .stop().set_bit()
+        });
+
+        // Wait for data to be ready
+        self.wait_event(|| i2c.sr1.read().rx_ne().bit_is_clear())?;
+
+        Ok(i2c.dr.read().dr().bits())
+    }
+
+    fn stop(&self) {
+        self.i2c.cr1.modify(|_, w| w.stop().set_bit());
+    }
+
+    fn wait_stop(&self) -> Result<(), I2cError> {
+
+        let i2c = self.i2c;
+
+        // STOP is cleared by hardware once generated, BUSY once it's seen
+        self.wait_event(|| i2c.cr1.read().stop().bit_is_set())?;
+        self.wait_event(|| i2c.sr2.read().busy().bit_is_set())?;
+
+        // Bus-free time, 1.3us for SCCB, doubled for margin
+        asm::delay(BUS_FREE);
+
+        Ok(())
+    }
+}
+
+// Report and clear a NACK (acknowledge failure)
+fn check_ack(i2c1: &stm32f401::I2C1) -> Result<(), I2cError> {
+
+    if i2c1.sr1.read().af().bit_is_set() {
+        i2c1.sr1.modify(|_, w| w.af().clear_bit());
+        return Err(I2cError::Nack);
+    }
+
+    Ok(())
+}
+
+// Wait for a bus event, tolerating clock stretching
+//
+// Some OV7670 clones hold SCL low while they work on a byte, which can
+// outlast `TIMEOUT`. SCL held low (`scl_held`) with the event still pending
+// means the device is stretching, so keep waiting up to `STRETCH_TIMEOUT`;
+// SCL released with nothing happening is a genuine hang.
+fn wait_event(mut pending: impl FnMut() -> bool, scl_held: impl Fn() -> bool) -> Result<(), I2cError> {
+
+    let mut waited = 0;
+
+    while !wait_while(TIMEOUT, &mut pending) {
+
+        waited += TIMEOUT;
+
+        if !scl_held() || waited >= STRETCH_TIMEOUT {
+            return Err(I2cError::Timeout);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use core::cell::RefCell;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Op {
+        Start,
+        Address { device: u8, read: bool },
+        Write(u8),
+        ReadLast,
+        Stop,
+        WaitStop
+    }
+
+    // Records every bus operation, NACKing addresses in the given direction
+    struct MockBus {
+        ops: RefCell<Vec<Op>>,
+        nack_read: Option<bool>,
+        data: u8
+    }
+
+    impl MockBus {
+        fn new(data: u8, nack_read: Option<bool>) -> Self {
+            MockBus { ops: RefCell::new(Vec::new()), nack_read, data }
+        }
+    }
+
+    impl I2cBus for MockBus {
+
+        fn start(&self) -> Result<(), I2cError> {
+            self.ops.borrow_mut().push(Op::Start);
+            Ok(())
+        }
+
+        fn address(&self, device: u8, read: bool) -> Result<(), I2cError> {
+            self.ops.borrow_mut().push(Op::Address { device, read });
+            match self.nack_read == Some(read) {
+                true => Err(I2cError::Nack),
+                false => Ok(())
+            }
+        }
+
+        fn write_byte(&self, byte: u8) -> Result<(), I2cError> {
+            self.ops.borrow_mut().push(Op::Write(byte));
+            Ok(())
+        }
+
+        fn read_last_byte(&self) -> Result<u8, I2cError> {
+            self.ops.borrow_mut().push(Op::ReadLast);
+            Ok(self.data)
+        }
+
+        fn stop(&self) {
+            self.ops.borrow_mut().push(Op::Stop);
+        }
+
+        fn wait_stop(&self) -> Result<(), I2cError> {
+            self.ops.borrow_mut().push(Op::WaitStop);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_is_one_transaction() {
+
+        let bus = MockBus::new(0, None);
+
+        assert_eq!(write(&bus, 0x21, 0x12, 0x80), Ok(()));
+        assert_eq!(*bus.ops.borrow(), [
+            Op::Start,
+            Op::Address { device: 0x21, read: false },
+            Op::Write(0x12),
+            Op::Write(0x80),
+            Op::Stop
+        ]);
+    }
+
+    #[test]
+    fn read_is_two_transactions_with_the_bus_freed_between() {
+
+        let bus = MockBus::new(0x76, None);
+
+        assert_eq!(read(&bus, 0x21, 0x0A), Ok(0x76));
+        assert_eq!(*bus.ops.borrow(), [
+            Op::Start,
+            Op::Address { device: 0x21, read: false },
+            Op::Write(0x0A),
+            Op::Stop,
+            Op::WaitStop,
+            Op::Start,
+            Op::Address { device: 0x21, read: true },
+            Op::ReadLast
+        ]);
+    }
+
+    #[test]
+    fn nacked_write_stops_without_sending_data() {
+
+        let bus = MockBus::new(0, Some(false));
+
+        assert_eq!(write(&bus, 0x21, 0x12, 0x80), Err(I2cError::Nack));
+        assert_eq!(*bus.ops.borrow(), [
+            Op::Start,
+            Op::Address { device: 0x21, read: false },
+            Op::Stop
+        ]);
+    }
+
+    #[test]
+    fn nacked_read_address_stops_without_reading() {
+
+        let bus = MockBus::new(0, Some(true));
+
+        assert_eq!(read(&bus, 0x21, 0x0A), Err(I2cError::Nack));
+        assert_eq!(bus.ops.borrow().last(), Some(&Op::Stop));
+        assert!(!bus.ops.borrow().contains(&Op::ReadLast));
+    }
+}
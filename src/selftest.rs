@@ -0,0 +1,64 @@
+use core::fmt::{self, Write};
+
+use crate::{camera::OV7670, display::ST7735};
+
+/// Report whether each peripheral initialized, printing PASS/FAIL per subsystem
+///
+/// Intended to be run once after `calibrate` during bring-up, before entering
+/// the capture loop.
+pub fn selftest(out: &mut impl Write, camera: &OV7670, display: &ST7735) -> fmt::Result {
+
+    write!(out, "Running self-test\r\n")?;
+
+    // Camera
+    report(out, "XCLK", camera.xclk_configured())?;
+    report(out, "Camera probe", camera.probe())?;
+    report(out, "I2C status", camera.i2c_status_ok())?;
+
+    // Display
+    match display.read_id() {
+        // SDA stuck low or floating high reads back all zeros or all ones
+        Ok(bytes @ [manufacturer, version, id]) => write!(
+            out,
+            "  {:<14}{} ({:02X} {:02X} {:02X})\r\n",
+            "Display ID",
+            if bytes == [0x00; 3] || bytes == [0xFF; 3] { "FAIL" } else { "PASS" },
+            manufacturer, version, id
+        )?,
+        Err(_) => report(out, "Display ID", false)?
+    }
+    match display.read_status() {
+        Ok(status) => report(out, "Display on", status.display_on && !status.sleeping)?,
+        Err(_) => report(out, "Display on", false)?
+    }
+    report(out, "SPI status", display.spi_status_ok())?;
+
+    Ok(())
+}
+
+fn report(out: &mut impl Write, name: &str, pass: bool) -> fmt::Result {
+    write!(out, "  {:<14}{}\r\n", name, if pass { "PASS" } else { "FAIL" })
+}
+
+/// Compare the per-byte cost of the generic and fast capture sampling paths
+///
+/// Times a PCLK check plus a data read through each path, the work between
+/// a PCLK edge and the data being latched, which bounds the fastest PCLK the
+/// capture loop can follow. Needs the DWT cycle counter enabled
+/// (`DCB::enable_trace` and `DWT::enable_cycle_counter`).
+pub fn benchmark_capture(out: &mut impl Write, camera: &OV7670) -> fmt::Result {
+
+    const ITERATIONS: u32 = 1000;
+
+    let Some((generic, fast)) = camera.sample_cycles(ITERATIONS) else {
+        return write!(out, "  {:<14}SKIP (cycle counter off)\r\n", "Capture bench");
+    };
+
+    write!(
+        out,
+        "  {:<14}{}.{:02} -> {}.{:02} cycles/byte\r\n",
+        "Capture bench",
+        generic / ITERATIONS, generic % ITERATIONS / 10,
+        fast / ITERATIONS, fast % ITERATIONS / 10
+    )
+}
@@ -0,0 +1,68 @@
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{self, Mutex};
+
+/*
+    Driver handle shared with interrupt handlers
+
+    Drivers borrow their GPIO ports, so to reach one from an ISR the PAC
+    peripherals must live for 'static first. Move them into a singleton,
+    construct the driver as usual, then install it in a `Shared` static:
+
+    static DISPLAY: Shared<ST7735<'static>> = Shared::new();
+
+    let dp = cortex_m::singleton!(: stm32f401::Peripherals = dp).unwrap();
+    let display = ST7735::new(&dp.RCC, &dp.GPIOA, &dp.SPI1, ControlPins::default(), Panel::default(), &Config::DEFAULT)?;
+    DISPLAY.install(display);
+
+    #[interrupt]
+    fn EXTI9_5() {
+        DISPLAY.with(|display| display.fill(None));
+    }
+
+    The value must be `Send`, as the drivers in this crate are. Every access
+    runs inside a critical section, so main and the ISRs never touch the
+    driver at the same time. Port registers are still shared
+    between drivers: avoid calls that reconfigure pin modes (e.g.
+    `OV7670::recover_bus`) while an ISR may use a driver on the same port.
+*/
+
+pub struct Shared<T>(Mutex<RefCell<Option<T>>>);
+
+// The same bound as `Mutex`: every accessor runs in a critical section, so
+// the value is only ever moved between main and the ISRs, never shared
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+
+    pub const fn new() -> Self {
+        Shared(Mutex::new(RefCell::new(None)))
+    }
+
+    /// Hand ownership of `value` to the handle, returning any previous value
+    pub fn install(&self, value: T) -> Option<T> {
+        interrupt::free(|cs| self.0.borrow(cs).replace(Some(value)))
+    }
+
+    /// Take ownership back, leaving the handle empty
+    pub fn take(&self) -> Option<T> {
+        interrupt::free(|cs| self.0.borrow(cs).take())
+    }
+
+    /// Run `f` on the value with interrupts disabled
+    ///
+    /// Returns `None` if nothing is installed or the value is already in use
+    /// further up the stack.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        interrupt::free(|cs| {
+            let mut value = self.0.borrow(cs).try_borrow_mut().ok()?;
+            value.as_mut().map(f)
+        })
+    }
+}
+
+impl<T> Default for Shared<T> {
+    fn default() -> Self {
+        Shared::new()
+    }
+}
@@ -0,0 +1,29 @@
+/// Spin while `condition` holds, giving up after `budget` polls
+///
+/// Each poll takes at least one core cycle, so a budget derived from `CLK_HZ`
+/// is a lower bound on the time waited. Returns `false` if the wait timed out.
+pub(crate) fn wait_while(budget: u32, mut condition: impl FnMut() -> bool) -> bool {
+
+    for _ in 0..budget {
+        if !condition() {
+            return true;
+        }
+    }
+
+    !condition()
+}
+
+/// Tries `retry_with_backoff` makes for bring-up waits, ~70ms in total from a
+/// 10ms budget
+#[cfg(any(feature = "camera", feature = "display"))]
+pub(crate) const INIT_ATTEMPTS: u32 = 3;
+
+/// Repeat a bounded wait, doubling its budget after every timeout
+///
+/// `attempt` is called with the budget for that try; it should (re)issue the
+/// request to the hardware and wait for it with `wait_while`. Returns `false`
+/// if all `attempts` timed out.
+#[cfg(any(feature = "camera", feature = "display"))]
+pub(crate) fn retry_with_backoff(budget: u32, attempts: u32, mut attempt: impl FnMut(u32) -> bool) -> bool {
+    (0..attempts).any(|n| attempt(budget.saturating_mul(1 << n.min(31))))
+}
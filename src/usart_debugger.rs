@@ -1,7 +1,8 @@
 use stm32f4::stm32f401;
 use core::fmt;
+use core::sync::atomic::{compiler_fence, Ordering};
 
-use super::constants::{BAUD_RATE, CLK_HZ};
+use super::{constants::Config, timeout::wait_while};
 
 /*
     USART over USB
@@ -9,47 +10,182 @@ use super::constants::{BAUD_RATE, CLK_HZ};
     CON|PIN|NOTE
     ==================
     TX |PA2|USART2_TX
+    RX |PA3|USART2_RX
+
+    With `with_dma`, transmission goes through DMA1 stream 6 channel 4
+    (USART2_TX) instead of polling TXE for every byte.
 */
 
+/// Failure reported by `UsartDebugger::write_dma`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TxError {
+    /// The transfer didn't finish in the time the baud rate allows
+    Timeout,
+    /// DMA1 reported a transfer error
+    Transfer
+}
+
+/// Sink for raw binary data, where `fmt::Write` would require UTF-8
+pub trait WriteBytes {
+    fn write_bytes(&mut self, bytes: &[u8]);
+}
+
 pub struct UsartDebugger {
-    usart: stm32f401::USART2
+    usart: stm32f401::USART2,
+    dma1: Option<stm32f401::DMA1>,
+    byte_cycles: u32
 }
 
 impl UsartDebugger {
 
+    // USART2_TX request mapping on DMA1
+    const STREAM: usize = 6;
+    const CHANNEL: u8 = 4;
+
     pub fn new(
         rcc: &stm32f401::RCC,
         gpioa: &stm32f401::GPIOA,
         usart2: stm32f401::USART2,
+        config: &Config
     ) -> Self {
 
         // Enable GPIOA clock
         rcc.ahb1enr.modify(|_, w| w.gpioaen().enabled());
 
-        // Configure TX pin to use an alternate function
-        gpioa.moder.modify(|_, w| w.moder2().alternate());
+        // Configure TX and RX pins to use an alternate function
+        gpioa.moder.modify(|_, w| {
+            w.moder2().alternate()
+             .moder3().alternate()
+        });
 
-        // Set PA2 to use USART2_TX
-        gpioa.afrl.modify(|_, w| w.afrl2().af7());
+        // Set PA2 to use USART2_TX and PA3 to use USART2_RX
+        gpioa.afrl.modify(|_, w| {
+            w.afrl2().af7()
+             .afrl3().af7()
+        });
 
         // Enable USART2 clock
         rcc.apb1enr.modify(|_, w| w.usart2en().enabled());
 
         // Set baud rate
-        usart2.brr.write(|w| unsafe { w.bits(CLK_HZ/BAUD_RATE) });
+        usart2.brr.write(|w| unsafe { w.bits(config.brr().into()) });
+
+        // Enable USART2 TX and RX
+        usart2.cr1.modify(|_, w| w.ue().enabled().te().enabled().re().enabled());
 
-        // Enable USART2 TX
-        usart2.cr1.modify(|_, w| w.ue().enabled().te().enabled());
+        // Start bit, 8 data bits and a stop bit, BRR core cycles each
+        let byte_cycles = 10 * config.brr() as u32;
 
-        UsartDebugger { usart: usart2 }
+        UsartDebugger { usart: usart2, dma1: None, byte_cycles }
     }
-}
 
-impl fmt::Write for UsartDebugger {
+    /// Transmit through DMA1 instead of polling, see `write_dma`
+    pub fn with_dma(mut self, rcc: &stm32f401::RCC, dma1: stm32f401::DMA1) -> Self {
 
-    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Enable DMA1 clock
+        rcc.ahb1enr.modify(|_, w| w.dma1en().enabled());
+
+        let stream = &dma1.st[UsartDebugger::STREAM];
+
+        stream.cr.write(|w| {
+            w.chsel().bits(UsartDebugger::CHANNEL)
+             .dir().memory_to_peripheral()
+             .psize().bits8()
+             .msize().bits8()
+             .pinc().fixed()
+             .minc().incremented()
+             .pl().low()
+        });
+
+        stream.par.write(|w| unsafe { w.pa().bits(self.usart.dr.as_ptr() as u32) });
+
+        // TXE raises a DMA request
+        self.usart.cr3.modify(|_, w| w.dmat().enabled());
+
+        self.dma1 = Some(dma1);
+        self
+    }
+
+    /// Send `buf` with DMA, blocking until the last byte has left the line
+    ///
+    /// The CPU only sets up each transfer (of up to 65535 bytes) and waits
+    /// for completion, which keeps long binary streams like `stream_frame`
+    /// at full baud. Falls back to polling without `with_dma`.
+    pub fn write_dma(&self, buf: &[u8]) -> Result<(), TxError> {
+
+        let Some(dma1) = &self.dma1 else {
+            self.write_polled(buf);
+            return Ok(());
+        };
+
+        let stream = &dma1.st[UsartDebugger::STREAM];
+
+        for chunk in buf.chunks(0xFFFF) {
+
+            dma1.hifcr.write(|w| {
+                w.ctcif6().set_bit()
+                 .chtif6().set_bit()
+                 .cteif6().set_bit()
+                 .cdmeif6().set_bit()
+                 .cfeif6().set_bit()
+            });
+
+            stream.m0ar.write(|w| unsafe { w.m0a().bits(chunk.as_ptr() as u32) });
+            stream.ndtr.write(|w| w.ndt().bits(chunk.len() as u16));
+
+            // TC is still set from the last transfer, clear it so the wait
+            // below sees this chunk finish
+            self.usart.sr.modify(|_, w| w.tc().clear_bit());
+
+            // The buffer must be written out before DMA reads it
+            compiler_fence(Ordering::SeqCst);
+
+            stream.cr.modify(|_, w| w.en().enabled());
+
+            let budget = (chunk.len() as u32 + 2).saturating_mul(self.byte_cycles);
 
-        for byte in s.bytes() {
+            let done = wait_while(budget, || {
+                let hisr = dma1.hisr.read();
+                hisr.tcif6().bit_is_clear() && hisr.teif6().bit_is_clear()
+            });
+
+            let failed = dma1.hisr.read().teif6().bit_is_set();
+
+            if !done || failed {
+                stream.cr.modify(|_, w| w.en().disabled());
+                return Err(if failed { TxError::Transfer } else { TxError::Timeout });
+            }
+
+            // DMA is done once the last byte is in DR, wait for it to shift out
+            if !wait_while(2 * self.byte_cycles, || self.usart.sr.read().tc().bit_is_clear()) {
+                return Err(TxError::Timeout);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take a received byte if one is waiting, without blocking
+    ///
+    /// Bytes that arrive faster than they're read are lost (overrun).
+    pub fn read_byte(&mut self) -> Option<u8> {
+
+        let sr = self.usart.sr.read();
+
+        // Reading SR then DR clears an overrun
+        if sr.rxne().bit_is_clear() && sr.ore().bit_is_clear() {
+            return None;
+        }
+
+        let byte = self.usart.dr.read().dr().bits() as u8;
+
+        sr.rxne().bit_is_set().then_some(byte)
+    }
+
+    // Send byte by byte, waiting on TXE
+    fn write_polled(&self, bytes: &[u8]) {
+
+        for &byte in bytes {
 
             // Wait for TX buffer to be empty
             while self.usart.sr.read().txe().bit_is_clear() {}
@@ -57,7 +193,21 @@ impl fmt::Write for UsartDebugger {
             // Write to data register
             self.usart.dr.write(|w| unsafe { w.bits(byte.into()) });
         }
+    }
+}
+
+impl WriteBytes for UsartDebugger {
 
+    // Output is best effort, like logging
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let _ = self.write_dma(bytes);
+    }
+}
+
+impl fmt::Write for UsartDebugger {
+
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
         Ok(())
     }
 }
@@ -0,0 +1,130 @@
+use crate::{
+    camera::{Camera, CaptureError, OV7670},
+    delay::DelayMs,
+    display::{Display, ST7735},
+    fault::FAULTS,
+    sccb::I2cError,
+    usart_debugger::UsartDebugger,
+    {error, info, warn}
+};
+
+/*
+    Viewfinder
+
+    The whole camera-to-display application as one type: owns the camera,
+    display and debug USART, brings them up together and streams frames with
+    capture failures recovered and logged.
+
+    let mut viewfinder = Viewfinder::new(camera, display, usart_debugger);
+    viewfinder.calibrate(&mut delay)?;
+    viewfinder.run(&mut delay, &mut |out, camera, display| { ... });
+*/
+
+/// Streams camera frames to the display, recovering from capture failures
+pub struct Viewfinder<'a> {
+    camera: OV7670<'a>,
+    display: ST7735<'a>,
+    debugger: UsartDebugger
+}
+
+impl<'a> Viewfinder<'a> {
+
+    // VSYNC timeouts in a row, with no frame between, before the camera is reset
+    const VSYNC_FAILURES_BEFORE_RESET: u32 = 3;
+
+    pub fn new(camera: OV7670<'a>, display: ST7735<'a>, debugger: UsartDebugger) -> Self {
+        Viewfinder { camera, display, debugger }
+    }
+
+    /// Setup and turn on the display, then the camera
+    ///
+    /// Progress and failures are logged to the debug USART.
+    pub fn calibrate(&mut self, delay: &mut impl DelayMs) -> Result<(), I2cError> {
+
+        info!(self.debugger, "Calibrating display");
+
+        self.display.calibrate(delay);
+
+        info!(self.debugger, "Calibrating camera");
+
+        self.camera.calibrate(delay).inspect_err(|error| {
+            error!(self.debugger, "Camera calibration failed ({:?})", error);
+        })
+    }
+
+    /// Stream frames forever
+    ///
+    /// `on_frame` is called once per frame during vertical blanking, see
+    /// `Camera::run`, after logging at most one of the faults the drivers
+    /// pushed to `FAULTS`; the rest are logged when a capture fails. A
+    /// failed capture is logged and the camera recalibrated;
+    /// after repeated VSYNC timeouts it is also hardware reset, since a
+    /// sensor knocked out by a brownout or ESD often won't recover from a
+    /// register rewrite alone.
+    pub fn run(
+        &mut self,
+        delay: &mut impl DelayMs,
+        on_frame: &mut impl FnMut(&mut UsartDebugger, &OV7670<'a>, &ST7735<'a>)
+    ) -> ! {
+
+        let mut vsync_failures = 0;
+
+        loop {
+            let mut streamed = false;
+
+            let error = self.camera.run(&self.display, &mut || {
+                streamed = true;
+                Viewfinder::log_fault(&mut self.debugger);
+                on_frame(&mut self.debugger, &self.camera, &self.display);
+            });
+
+            Viewfinder::log_faults(&mut self.debugger);
+
+            warn!(self.debugger, "Capture failed ({:?}), recalibrating camera", error);
+
+            vsync_failures = match (error, streamed) {
+                (CaptureError::VsyncTimeout, false) => vsync_failures + 1,
+                (CaptureError::VsyncTimeout, true) => 1,
+                _ => 0
+            };
+
+            if vsync_failures >= Viewfinder::VSYNC_FAILURES_BEFORE_RESET {
+                warn!(self.debugger, "No VSYNC after {} attempts, resetting camera", vsync_failures);
+                if !self.camera.hardware_reset(delay) {
+                    warn!(self.debugger, "No RESET pin configured, recalibrating only");
+                }
+                vsync_failures = 0;
+            }
+
+            if let Err(error) = self.camera.calibrate(delay) {
+                error!(self.debugger, "Camera calibration failed ({:?})", error);
+            }
+        }
+    }
+
+    // Log the oldest fault a driver recovered from, if any
+    //
+    // Called between frames, where the vertical blanking doesn't leave time
+    // to drain the whole log
+    fn log_fault(debugger: &mut UsartDebugger) {
+        if let Some((fault, count)) = FAULTS.pop() {
+            warn!(*debugger, "{:?} (x{})", fault, count);
+        }
+    }
+
+    // Log the faults the drivers recovered from since the last call
+    fn log_faults(debugger: &mut UsartDebugger) {
+
+        FAULTS.drain(|fault, count| warn!(*debugger, "{:?} (x{})", fault, count));
+
+        let dropped = FAULTS.take_dropped();
+        if dropped > 0 {
+            warn!(*debugger, "{} more faults dropped", dropped);
+        }
+    }
+
+    /// Borrow the drivers, e.g. to run `selftest` or change settings
+    pub fn parts(&mut self) -> (&mut UsartDebugger, &OV7670<'a>, &ST7735<'a>) {
+        (&mut self.debugger, &self.camera, &self.display)
+    }
+}